@@ -4,15 +4,123 @@ use crate::rlox::lox::Lox;
 
 use std::env;
 
+const USAGE: &str =
+    "Usage: rlox [--help] [--version] [--profile] [--coverage] [--strict] [--len-truthy] [--allow-io] [--budget N] [--seed N] [script | -e source | --format script | --source-map script]";
+
+/// What the command line asks rlox to do.
+#[derive(Debug, PartialEq)]
+enum Command {
+    /// Print a message, like the usage or the version, and do nothing else.
+    Print(String),
+    Prompt,
+    File(String),
+    Eval(String),
+    Format(String),
+    SourceMap(String),
+}
+
 fn main() {
-    let mut args = env::args();
-    args.next();
-
-    if args.len() == 0 {
-        Lox::run_prompt().unwrap();
-    } else if args.len() == 1 {
-        Lox::run_file(args.next().unwrap().into()).unwrap();
-    } else {
-        println!("Usage: rlox [script]")
+    match parse_args(env::args().skip(1)) {
+        Command::Print(message) => println!("{message}"),
+        Command::Prompt => Lox::run_prompt().unwrap(),
+        Command::File(script) => Lox::run_file(script.into()).unwrap(),
+        Command::Eval(source) => Lox::run_eval(source).unwrap(),
+        Command::Format(script) => Lox::format_file(script.into()).unwrap(),
+        Command::SourceMap(script) => Lox::source_map_file(script.into()).unwrap(),
+    }
+}
+
+/// Flags that change how a program runs are passed on through environment
+/// variables, which the rest of rlox reads.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Command {
+    let usage = || Command::Print(USAGE.into());
+
+    let mut scripts = vec![];
+    let mut eval = None;
+    let mut format = false;
+    let mut source_map = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return usage(),
+            "-V" | "--version" => {
+                return Command::Print(format!("rlox {}", env!("CARGO_PKG_VERSION")))
+            }
+            "--profile" => env::set_var("RLOX_PROFILE", "1"),
+            "--coverage" => env::set_var("RLOX_COVERAGE", "1"),
+            "--strict" => env::set_var("RLOX_STRICT", "1"),
+            "--len-truthy" => env::set_var("RLOX_LEN_TRUTHY", "1"),
+            "--allow-io" => env::set_var("RLOX_ALLOW_IO", "1"),
+            "--format" => format = true,
+            "--source-map" => source_map = true,
+            "--budget" => match args.next() {
+                Some(budget) if budget.parse::<usize>().is_ok() => {
+                    env::set_var("RLOX_BUDGET", budget)
+                }
+                _ => return usage(),
+            },
+            "--seed" => match args.next() {
+                Some(seed) if seed.parse::<u64>().is_ok() => env::set_var("RLOX_SEED", seed),
+                _ => return usage(),
+            },
+            "-e" | "--eval" => match args.next() {
+                Some(source) => eval = Some(source),
+                None => return usage(),
+            },
+            flag if flag.starts_with('-') => {
+                return Command::Print(format!("Unknown option `{flag}`.\n{USAGE}"))
+            }
+            _ => scripts.push(arg),
+        }
+    }
+
+    if scripts.len() > 1 {
+        return usage();
+    }
+    match (scripts.pop(), eval) {
+        (Some(script), _) if format => Command::Format(script),
+        (Some(script), _) if source_map => Command::SourceMap(script),
+        (None, _) if format || source_map => usage(),
+        (Some(_), Some(_)) => usage(),
+        (Some(script), None) => Command::File(script),
+        (None, Some(source)) => Command::Eval(source),
+        (None, None) => Command::Prompt,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_args, Command, USAGE};
+
+    fn parse(args: &[&str]) -> Command {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn version_and_unknown_flags_print_instead_of_running() {
+        let version = format!("rlox {}", env!("CARGO_PKG_VERSION"));
+        assert_eq!(parse(&["--version"]), Command::Print(version.clone()));
+        assert_eq!(parse(&["-V", "script.lox"]), Command::Print(version));
+        assert_eq!(
+            parse(&["--frobnicate", "script.lox"]),
+            Command::Print(format!("Unknown option `--frobnicate`.\n{USAGE}"))
+        );
+    }
+
+    #[test]
+    fn arguments_pick_what_to_run() {
+        assert_eq!(parse(&[]), Command::Prompt);
+        assert_eq!(parse(&["a.lox"]), Command::File("a.lox".into()));
+        assert_eq!(parse(&["-e", "1+2"]), Command::Eval("1+2".into()));
+        assert_eq!(
+            parse(&["--format", "a.lox"]),
+            Command::Format("a.lox".into())
+        );
+        assert_eq!(
+            parse(&["--source-map", "a.lox"]),
+            Command::SourceMap("a.lox".into())
+        );
+        assert_eq!(parse(&["a.lox", "b.lox"]), Command::Print(USAGE.into()));
+        assert_eq!(parse(&["--budget", "x"]), Command::Print(USAGE.into()));
     }
 }
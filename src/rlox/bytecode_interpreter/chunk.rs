@@ -1,4 +1,8 @@
-use std::fmt::{Debug, Display};
+use std::fmt::{Debug, Display, Write};
+use std::rc::Rc;
+
+use crate::rlox::error::LoxError;
+use crate::rlox::types::{Function, Literal};
 
 use super::opcode::OpCode;
 
@@ -50,6 +54,71 @@ impl Chunk {
     pub fn len(&self) -> usize {
         self.codes.len()
     }
+
+    /// The functions defined in this chunk, in the order they are loaded.
+    pub fn functions(&self) -> impl Iterator<Item = &Rc<Function>> {
+        self.codes.iter().filter_map(|code| match code {
+            OpCode::Load(Literal::Function(function)) => Some(function),
+            _ => None,
+        })
+    }
+
+    /// Pairs the instructions with the `line:column` they were compiled from.
+    /// Runs of instructions from the same position share one `first-last`
+    /// entry to keep the map short.
+    pub fn source_map(&self) -> String {
+        let mut map = String::new();
+        let mut start = 0;
+
+        for i in 1..=self.positions.len() {
+            if i < self.positions.len() && self.positions[i] == self.positions[start] {
+                continue;
+            }
+
+            let (line, column) = self.positions[start];
+            let range = if i - 1 == start {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, i - 1)
+            };
+            writeln!(map, "{range} {line}:{column}").unwrap();
+            start = i;
+        }
+
+        map
+    }
+
+    /// Checks that every jump lands on an instruction of this chunk, which
+    /// catches jumps the convertor left unpatched or patched wrongly.
+    pub fn validate(&self) -> Result<(), LoxError> {
+        let bad_jumps = self
+            .codes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, code)| {
+                // the ip has already moved past the jump when it is applied
+                let target = match code {
+                    OpCode::Jump(offset)
+                    | OpCode::JumpIfTrue(offset)
+                    | OpCode::JumpIfFalse(offset) => (i + 1).checked_add(*offset),
+                    OpCode::JumpForward(offset) => (i + 1).checked_sub(*offset),
+                    _ => return None,
+                };
+                match target {
+                    Some(target) if target < self.len() => None,
+                    _ => Some(format!("[{:>4}]: {:?}", i, code)),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if bad_jumps.is_empty() {
+            Ok(())
+        } else {
+            Err(LoxError::UnexpectedError {
+                message: format!("Jump out of chunk:\n{}", bad_jumps.join("\n")),
+            })
+        }
+    }
 }
 
 impl Debug for Chunk {
@@ -66,3 +135,40 @@ impl Display for Chunk {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Chunk;
+    use crate::rlox::bytecode_interpreter::opcode::OpCode;
+    use crate::rlox::lox::testing::{compile, message};
+    use crate::rlox::types::Literal;
+
+    #[test]
+    fn validate_catches_jumps_out_of_the_chunk() {
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::Jump(1), (1, 1));
+        chunk.write(OpCode::Load(Literal::Nil), (1, 1));
+        chunk.write(OpCode::Return, (1, 1));
+        assert!(chunk.validate().is_ok());
+
+        chunk.write(OpCode::JumpIfFalse(5), (2, 1));
+        chunk.write(OpCode::JumpForward(10), (3, 1));
+        let err = chunk.validate().unwrap_err();
+        let message = message(&err);
+        assert!(message.contains("[   3]"));
+        assert!(message.contains("[   4]"));
+        assert!(!message.contains("[   0]"));
+    }
+
+    #[test]
+    fn source_map_groups_instructions_by_position() {
+        let function = compile("print 1;\nprint 2 + 3;").unwrap();
+        let chunk = &function.chunk;
+        assert!(matches!(chunk.get(1), Some(OpCode::Print(_))));
+        assert!(matches!(chunk.get(4), Some(OpCode::Add)));
+        assert_eq!(
+            chunk.source_map(),
+            "0 1:6\n1 1:0\n2 2:6\n3 2:10\n4 2:8\n5 2:0\n6-7 0:0\n"
+        );
+    }
+}
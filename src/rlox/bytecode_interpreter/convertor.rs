@@ -1,11 +1,13 @@
 #![allow(unused)]
 
+use std::collections::HashMap;
 use std::rc::Rc;
 
 use crate::rlox::{
     error::LoxError,
     expr::{Expression, Visitor as ExprVisitor},
-    stmt::{Statement, Visitor as StmtVisitor},
+    stmt::{FunctionStatement, Statement, Visitor as StmtVisitor},
+    token::Token,
     types::{FuncType, Function, Literal, TokenType},
 };
 
@@ -18,7 +20,7 @@ pub struct Convertor {
     break_position: Vec<usize>,
     continue_position: Vec<usize>,
     loop_body_depth: usize,
-    is_returned: bool,
+    keep_last_value: bool,
 }
 
 impl Default for Convertor {
@@ -30,7 +32,7 @@ impl Default for Convertor {
             break_position: Default::default(),
             continue_position: Default::default(),
             loop_body_depth: Default::default(),
-            is_returned: Default::default(),
+            keep_last_value: Default::default(),
         }
     }
 }
@@ -38,7 +40,12 @@ impl Default for Convertor {
 impl Convertor {
     pub fn new(func_name: &str, func_type: FuncType) -> Self {
         let mut scopes: Scopes = Default::default();
-        scopes.define_variable(Rc::new(func_name.into()), 0);
+        // Slot 0 holds the callee, which methods see as `self`.
+        let slot_zero = match func_type {
+            FuncType::Method | FuncType::Initializer => "self",
+            _ => func_name,
+        };
+        scopes.define_variable(Rc::new(slot_zero.into()), 0, (0, 0));
 
         if FuncType::Main != func_type {
             scopes.begin_scope();
@@ -51,24 +58,41 @@ impl Convertor {
             break_position: vec![],
             continue_position: vec![],
             loop_body_depth: 0,
-            is_returned: false,
+            keep_last_value: false,
         }
     }
 
+    /// Makes a trailing expression statement the return value of the chunk
+    /// instead of discarding it, which is how the REPL gets line results.
+    pub fn keep_last_value(&mut self) {
+        self.keep_last_value = true;
+    }
+
     fn current_chunk(&mut self) -> &mut Chunk {
         &mut self.function.chunk
     }
 
     pub fn convert(mut self, statements: &[Statement]) -> Result<Function, LoxError> {
-        for stmt in statements {
-            self.convert_statement(stmt)?;
+        if self.keep_last_value {
+            if let Some((Statement::ExpressionStatement(last), statements)) =
+                statements.split_last()
+            {
+                self.convert_statements(statements)?;
+                self.convert_expression(&last.expression)?;
+                self.current_chunk()
+                    .write(OpCode::Return, last.end.position);
+                return self.finish();
+            }
         }
+
+        self.convert_statements(statements)?;
         // println!("{}", self.current_chunk());
         // println!("{:#?}", self.scopes);
 
-        if !self.is_returned {
-            self.current_chunk()
-                .write(OpCode::Load(Literal::Nil), (0, 0));
+        // A `return` nested in a branch doesn't cover every path, so the
+        // implicit `return nil` is only left out after a trailing `return`.
+        if !matches!(statements.last(), Some(Statement::ReturnStatement(_))) {
+            self.load_return_value((0, 0));
             self.current_chunk().write(OpCode::Return, (0, 0));
         }
 
@@ -76,6 +100,13 @@ impl Convertor {
             self.scopes.end_scope();
         }
 
+        self.finish()
+    }
+
+    fn finish(self) -> Result<Function, LoxError> {
+        if cfg!(debug_assertions) {
+            self.function.chunk.validate()?;
+        }
         Ok(self.function)
     }
 
@@ -87,13 +118,131 @@ impl Convertor {
         stmt.accept(self)
     }
 
+    /// Function declarations are hoisted to the top of their block, so a
+    /// function can be called above the line that declares it. Since nested
+    /// functions can't capture locals, mutual recursion still only works
+    /// between top-level functions. A function whose name is declared again
+    /// in the same block stays in place, so that whichever declaration comes
+    /// later in the source still wins.
     fn convert_statements(&mut self, statements: &[Statement]) -> Result<(), LoxError> {
+        let mut declarations: HashMap<&str, usize> = HashMap::new();
         for stmt in statements {
+            match stmt {
+                Statement::FunctionStatement(function) => {
+                    *declarations.entry(&function.name.lexeme).or_default() += 1
+                }
+                Statement::ClassStatement(class) => {
+                    *declarations.entry(&class.name.lexeme).or_default() += 1
+                }
+                Statement::MultiVarStatement(multi_var) => {
+                    for var in &multi_var.vars {
+                        if let Statement::VarStatement(var) = var {
+                            *declarations.entry(&var.name.lexeme).or_default() += 1
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let (functions, others): (Vec<_>, Vec<_>) = statements.iter().partition(|stmt| {
+            matches!(stmt, Statement::FunctionStatement(function)
+                if declarations[function.name.lexeme.as_str()] == 1)
+        });
+
+        for stmt in functions.into_iter().chain(others) {
             stmt.accept(self)?;
         }
         Ok(())
     }
 
+    /// `a + x` with a local `x` compiles to one opcode that reads the slot
+    /// itself, rather than a `GetLocal` followed by the operator. The flag
+    /// tells whether a `Not` has to follow, as for `<=` and `>=`.
+    fn fuse_local_operand(
+        &self,
+        binary_expression: &crate::rlox::expr::BinaryExpression,
+    ) -> Option<(OpCode, bool)> {
+        let Expression::VariableExpression(variable) = binary_expression.right.as_ref() else {
+            return None;
+        };
+        let slot = self
+            .scopes
+            .find_variable(variable.name.lexeme.clone())
+            .ok()?;
+
+        Some(match binary_expression.op.token_type {
+            TokenType::Plus => (OpCode::AddLocal(slot), false),
+            TokenType::Minus => (OpCode::SubLocal(slot), false),
+            TokenType::Star => (OpCode::MulLocal(slot), false),
+            TokenType::Slash => (OpCode::DivLocal(slot), false),
+            TokenType::Less => (OpCode::LessLocal(slot), false),
+            TokenType::GreaterEqual => (OpCode::LessLocal(slot), true),
+            TokenType::Greater => (OpCode::GreaterLocal(slot), false),
+            TokenType::LessEqual => (OpCode::GreaterLocal(slot), true),
+            _ => return None,
+        })
+    }
+
+    /// An initializer always gives back the instance, and anything else
+    /// returns `nil` when no value is given.
+    fn load_return_value(&mut self, position: (usize, usize)) {
+        if FuncType::Initializer == self.func_type {
+            self.current_chunk().write(OpCode::GetLocal(0), position);
+        } else {
+            self.current_chunk()
+                .write(OpCode::Load(Literal::Nil), position);
+        }
+    }
+
+    fn convert_function(
+        &mut self,
+        function_statement: &FunctionStatement,
+        func_type: FuncType,
+    ) -> Result<Rc<Function>, LoxError> {
+        let mut convertor = Convertor::new(&function_statement.name.lexeme, func_type);
+
+        let depth = convertor.scopes.depth;
+        for param in &function_statement.params {
+            convertor.check_local_limit(param)?;
+            convertor
+                .scopes
+                .define_variable(param.lexeme.clone(), depth, param.position);
+        }
+
+        let mut func = convertor.convert(&function_statement.body)?;
+        func.arity = function_statement.params.len();
+
+        Ok(Rc::new(func))
+    }
+
+    /// Stores the value on top of the stack under `name`, as a global at the
+    /// top level and as a local anywhere else.
+    fn define_named(&mut self, name: &Token) -> Result<(), LoxError> {
+        if self.scopes.depth == 0 {
+            self.current_chunk()
+                .write(OpCode::DefineGlobal(name.lexeme.clone()), name.position);
+        } else {
+            self.check_local_limit(name)?;
+            self.scopes
+                .define_variable(name.lexeme.clone(), self.scopes.depth, name.position);
+        }
+        Ok(())
+    }
+
+    fn check_local_limit(&self, name: &Token) -> Result<(), LoxError> {
+        if self.scopes.is_full() {
+            return Err(LoxError::ParseError {
+                position: name.position,
+                end: name.end,
+                lexeme: name.display_lexeme(),
+                token_type: name.token_type,
+                msg: "Too many local variables in function.".into(),
+            });
+        }
+        Ok(())
+    }
+
     fn patch_jump_opcode(&mut self, index: usize) {
         let cur = self.current_chunk().len() - 1;
         let code = self.current_chunk().get_mut(index).unwrap();
@@ -105,26 +254,26 @@ impl Convertor {
         }
     }
 
-    fn handle_continue_jump(&mut self) {
-        if !self.continue_position.is_empty() {
-            let cur = self.current_chunk().len() - 1;
-            let pos = self.continue_position.pop().unwrap();
-            let code = self.current_chunk().get_mut(pos).unwrap();
-            if let OpCode::Jump(offset) = code {
-                *offset = cur - pos
-            };
-        }
+    /// Points the `continue` jumps of the current loop, those pushed after
+    /// `mark`, at the next instruction to be written.
+    fn handle_continue_jump(&mut self, mark: usize) {
+        let positions = self.continue_position.split_off(mark);
+        self.patch_loop_jumps(positions);
     }
 
-    fn handle_break_jump(&mut self) {
-        if !self.break_position.is_empty() {
-            let cur = self.current_chunk().len() - 1;
-            let pos = self.continue_position.pop().unwrap();
-            let code = self.current_chunk().get_mut(pos).unwrap();
-            if let OpCode::Jump(offset) = code {
+    /// Points the `break` jumps of the current loop, those pushed after
+    /// `mark`, just past the loop.
+    fn handle_break_jump(&mut self, mark: usize) {
+        let positions = self.break_position.split_off(mark);
+        self.patch_loop_jumps(positions);
+    }
+
+    fn patch_loop_jumps(&mut self, positions: Vec<usize>) {
+        let cur = self.current_chunk().len() - 1;
+        for pos in positions {
+            if let Some(OpCode::Jump(offset)) = self.current_chunk().get_mut(pos) {
                 *offset = cur - pos
             }
-            self.break_position.pop();
         }
     }
 
@@ -170,11 +319,33 @@ impl ExprVisitor<(), LoxError> for Convertor {
         &mut self,
         binary_expression: &crate::rlox::expr::BinaryExpression,
     ) -> Result<(), LoxError> {
+        if binary_expression.op.token_type == TokenType::Plus {
+            if let Some(string) = fold_string(&binary_expression.left)
+                .zip(fold_string(&binary_expression.right))
+                .map(|(left, right)| left + &right)
+            {
+                self.current_chunk().write(
+                    OpCode::Load(Literal::String(Rc::new(string))),
+                    binary_expression.op.position,
+                );
+                return Ok(());
+            }
+        }
+
         self.convert_expression(&binary_expression.left)?;
-        self.convert_expression(&binary_expression.right)?;
 
         let pos = binary_expression.op.position;
 
+        if let Some((opcode, negate)) = self.fuse_local_operand(binary_expression) {
+            self.current_chunk().write(opcode, pos);
+            if negate {
+                self.current_chunk().write(OpCode::Not, pos);
+            }
+            return Ok(());
+        }
+
+        self.convert_expression(&binary_expression.right)?;
+
         match binary_expression.op.token_type {
             TokenType::Plus => {
                 self.function.chunk.write(OpCode::Add, pos);
@@ -226,7 +397,7 @@ impl ExprVisitor<(), LoxError> for Convertor {
         &mut self,
         call_expression: &crate::rlox::expr::CallExpression,
     ) -> Result<(), LoxError> {
-        self.convert_expression(&call_expression.callee);
+        self.convert_expression(&call_expression.callee)?;
         for arg in &call_expression.arguments {
             self.convert_expression(arg)?;
         }
@@ -241,7 +412,64 @@ impl ExprVisitor<(), LoxError> for Convertor {
         &mut self,
         get_expression: &crate::rlox::expr::GetExpression,
     ) -> Result<(), LoxError> {
-        todo!()
+        self.convert_expression(&get_expression.object)?;
+        self.current_chunk().write(
+            OpCode::GetProperty(get_expression.name.lexeme.clone()),
+            get_expression.name.position,
+        );
+        Ok(())
+    }
+
+    fn visit_array_expression(
+        &mut self,
+        array_expression: &crate::rlox::expr::ArrayExpression,
+    ) -> Result<(), LoxError> {
+        for element in &array_expression.elements {
+            self.convert_expression(element)?;
+        }
+        self.current_chunk().write(
+            OpCode::BuildArray(array_expression.elements.len()),
+            array_expression.bracket.position,
+        );
+        Ok(())
+    }
+
+    fn visit_map_expression(
+        &mut self,
+        map_expression: &crate::rlox::expr::MapExpression,
+    ) -> Result<(), LoxError> {
+        for (key, value) in &map_expression.entries {
+            self.convert_expression(key)?;
+            self.convert_expression(value)?;
+        }
+        self.current_chunk().write(
+            OpCode::BuildMap(map_expression.entries.len()),
+            map_expression.brace.position,
+        );
+        Ok(())
+    }
+
+    fn visit_index_expression(
+        &mut self,
+        index_expression: &crate::rlox::expr::IndexExpression,
+    ) -> Result<(), LoxError> {
+        self.convert_expression(&index_expression.object)?;
+        self.convert_expression(&index_expression.index)?;
+        self.current_chunk()
+            .write(OpCode::Index, index_expression.bracket.position);
+        Ok(())
+    }
+
+    fn visit_index_set_expression(
+        &mut self,
+        index_set_expression: &crate::rlox::expr::IndexSetExpression,
+    ) -> Result<(), LoxError> {
+        self.convert_expression(&index_set_expression.object)?;
+        self.convert_expression(&index_set_expression.index)?;
+        self.convert_expression(&index_set_expression.value)?;
+        self.current_chunk()
+            .write(OpCode::SetIndex, index_set_expression.bracket.position);
+        Ok(())
     }
 
     fn visit_grouping_expression(
@@ -298,21 +526,52 @@ impl ExprVisitor<(), LoxError> for Convertor {
         &mut self,
         set_expression: &crate::rlox::expr::SetExpression,
     ) -> Result<(), LoxError> {
-        todo!()
+        self.convert_expression(&set_expression.object)?;
+        self.convert_expression(&set_expression.value)?;
+        self.current_chunk().write(
+            OpCode::SetProperty(set_expression.name.lexeme.clone()),
+            set_expression.name.position,
+        );
+        Ok(())
     }
 
     fn visit_super_expression(
         &mut self,
         super_expression: &crate::rlox::expr::SuperExpression,
     ) -> Result<(), LoxError> {
-        todo!()
+        let Ok(slot) = self.scopes.find_variable(Rc::new("self".into())) else {
+            return Err(LoxError::create_runtime_error(
+                &super_expression.keyword,
+                "Keyword `super` can only be used directly in a method.".into(),
+            ));
+        };
+        self.current_chunk()
+            .write(OpCode::GetLocal(slot), super_expression.keyword.position);
+        self.current_chunk().write(
+            OpCode::GetSuper(super_expression.method.lexeme.clone()),
+            super_expression.method.position,
+        );
+        Ok(())
     }
 
     fn visit_self_expression(
         &mut self,
         self_expression: &crate::rlox::expr::SelfExpression,
     ) -> Result<(), LoxError> {
-        todo!()
+        match self
+            .scopes
+            .find_variable(self_expression.keyword.lexeme.clone())
+        {
+            Ok(slot) => {
+                self.current_chunk()
+                    .write(OpCode::GetLocal(slot), self_expression.keyword.position);
+                Ok(())
+            }
+            Err(_) => Err(LoxError::create_runtime_error(
+                &self_expression.keyword,
+                "Keyword `self` can only be used directly in a method.".into(),
+            )),
+        }
     }
 
     fn visit_ternary_expression(
@@ -391,12 +650,15 @@ impl ExprVisitor<(), LoxError> for Convertor {
 
         let depth = convertor.scopes.depth;
         for param in &lambda_expression.params {
+            convertor.check_local_limit(param)?;
             convertor
                 .scopes
-                .define_variable(param.lexeme.clone(), depth);
+                .define_variable(param.lexeme.clone(), depth, param.position);
         }
 
         let mut func = convertor.convert(&lambda_expression.body)?;
+        func.arity = arity;
+        func.position = lambda_expression.keyword.position;
 
         let func = Rc::new(func);
         self.current_chunk()
@@ -405,6 +667,33 @@ impl ExprVisitor<(), LoxError> for Convertor {
         Ok(())
     }
 
+    fn visit_multi_assign_expression(
+        &mut self,
+        multi_assign_expression: &crate::rlox::expr::MultiAssignExpression,
+    ) -> Result<(), LoxError> {
+        for value in &multi_assign_expression.values {
+            self.convert_expression(value)?;
+        }
+
+        // The values are on the stack in order, so they are assigned from
+        // the last one down, leaving the first as the expression's value.
+        for (i, name) in multi_assign_expression.names.iter().enumerate().rev() {
+            match self.scopes.find_variable(name.lexeme.clone()) {
+                Ok(slot) => self
+                    .current_chunk()
+                    .write(OpCode::SetLocal(slot), name.position),
+                Err(_) => self
+                    .current_chunk()
+                    .write(OpCode::SetGlobal(name.lexeme.clone()), name.position),
+            };
+            if i > 0 {
+                self.current_chunk().write(OpCode::Pop, name.position);
+            }
+        }
+
+        Ok(())
+    }
+
     fn visit_operate_and_assign_expression(
         &mut self,
         operate_and_assign_expression: &crate::rlox::expr::OperateAndAssignExpression,
@@ -482,7 +771,7 @@ impl StmtVisitor<(), LoxError> for Convertor {
         self.convert_expression(&expression_statement.expression)?;
         self.function
             .chunk
-            .write(OpCode::Pop, expression_statement.end.position);
+            .write(OpCode::Discard, expression_statement.end.position);
         Ok(())
     }
 
@@ -490,10 +779,25 @@ impl StmtVisitor<(), LoxError> for Convertor {
         &mut self,
         print_statement: &crate::rlox::stmt::PrintStatement,
     ) -> Result<(), LoxError> {
-        self.convert_expression(&print_statement.expression)?;
-        self.function
-            .chunk
-            .write(OpCode::Print, print_statement.keyword.position);
+        for expression in &print_statement.expressions {
+            self.convert_expression(expression)?;
+        }
+        self.function.chunk.write(
+            OpCode::Print(print_statement.expressions.len()),
+            print_statement.keyword.position,
+        );
+        Ok(())
+    }
+
+    fn visit_assert_statement(
+        &mut self,
+        assert_statement: &crate::rlox::stmt::AssertStatement,
+    ) -> Result<(), LoxError> {
+        self.convert_expression(&assert_statement.condition)?;
+        self.function.chunk.write(
+            OpCode::Assert(Rc::new(assert_statement.condition.to_string())),
+            assert_statement.keyword.position,
+        );
         Ok(())
     }
 
@@ -510,14 +814,18 @@ impl StmtVisitor<(), LoxError> for Convertor {
         }
 
         if self.scopes.depth > 0 {
-            if self
-                .scopes
-                .define_variable(var_statement.name.lexeme.clone(), self.scopes.depth)
-                .is_err()
-            {
+            self.check_local_limit(&var_statement.name)?;
+            if let Err(previous) = self.scopes.define_variable(
+                var_statement.name.lexeme.clone(),
+                self.scopes.depth,
+                var_statement.name.position,
+            ) {
                 return Err(LoxError::create_runtime_error(
                     &var_statement.name,
-                    "Already a variable with this name in this scope.".into(),
+                    format!(
+                        "Already a variable with this name in this scope, previously declared at [{},{}].",
+                        previous.0, previous.1
+                    ),
                 ));
             };
         } else {
@@ -561,16 +869,15 @@ impl StmtVisitor<(), LoxError> for Convertor {
 
         self.convert_statement(&branch_statement.then_branch)?;
 
+        // The condition is popped on both paths, so the taken branch has to
+        // jump over the `Pop` of the other one even when there is no `else`.
+        let jump = self.current_chunk().write(OpCode::Jump(0), (0, 0));
+        self.patch_jump_opcode(jump_false);
+        self.current_chunk().write(OpCode::Pop, (0, 0));
         if let Some(eb) = &branch_statement.else_branch {
-            let jump = self.current_chunk().write(OpCode::Jump(0), (0, 0));
-            self.patch_jump_opcode(jump_false);
-            self.current_chunk().write(OpCode::Pop, (0, 0));
             self.convert_statement(eb)?;
-            self.patch_jump_opcode(jump);
-        } else {
-            self.patch_jump_opcode(jump_false);
-            self.current_chunk().write(OpCode::Pop, (0, 0));
         }
+        self.patch_jump_opcode(jump);
 
         Ok(())
     }
@@ -580,6 +887,8 @@ impl StmtVisitor<(), LoxError> for Convertor {
         while_statement: &crate::rlox::stmt::WhileStatement,
     ) -> Result<(), LoxError> {
         let loop_start = self.current_chunk().len();
+        let breaks = self.break_position.len();
+        let continues = self.continue_position.len();
         let pre = self.loop_body_depth;
         self.loop_body_depth = self.scopes.depth;
 
@@ -587,7 +896,7 @@ impl StmtVisitor<(), LoxError> for Convertor {
         let jump_false = self.current_chunk().write(OpCode::JumpIfFalse(0), (0, 0));
         self.current_chunk().write(OpCode::Pop, (0, 0));
         self.convert_statement(&while_statement.body)?;
-        self.handle_continue_jump();
+        self.handle_continue_jump(continues);
 
         if let Some(incr) = &while_statement.increment {
             self.convert_statement(incr)?;
@@ -598,7 +907,7 @@ impl StmtVisitor<(), LoxError> for Convertor {
             .write(OpCode::JumpForward(cur - loop_start + 1), (0, 0));
         self.patch_jump_opcode(jump_false);
         self.current_chunk().write(OpCode::Pop, (0, 0));
-        self.handle_break_jump();
+        self.handle_break_jump(breaks);
 
         self.loop_body_depth = pre;
 
@@ -645,47 +954,21 @@ impl StmtVisitor<(), LoxError> for Convertor {
         &mut self,
         function_statement: &crate::rlox::stmt::FunctionStatement,
     ) -> Result<(), LoxError> {
-        let name = function_statement.name.lexeme.clone();
-        let arity = function_statement.params.len();
-        let mut convertor = Convertor::new(&name, FuncType::Normal);
-
-        let depth = convertor.scopes.depth;
-        for param in &function_statement.params {
-            convertor
-                .scopes
-                .define_variable(param.lexeme.clone(), depth);
-        }
-
-        let mut func = convertor.convert(&function_statement.body)?;
-        func.arity = arity;
-
-        let func = Rc::new(func);
+        let func = self.convert_function(function_statement, FuncType::Normal)?;
         self.current_chunk()
             .write(OpCode::Load(func.into()), function_statement.name.position);
 
-        // self.current_chunk()
-        //     .write(OpCode::DefineGlobal(name), function_statement.name.position);
-        if self.scopes.depth == 0 {
-            self.current_chunk()
-                .write(OpCode::DefineGlobal(name), function_statement.name.position);
-        } else {
-            self.scopes.define_variable(name, self.scopes.depth);
-        }
-        Ok(())
+        self.define_named(&function_statement.name)
     }
 
     fn visit_return_statement(
         &mut self,
         return_statement: &crate::rlox::stmt::ReturnStatement,
     ) -> Result<(), LoxError> {
-        self.is_returned = true;
         if let Some(value) = &return_statement.value {
             self.convert_expression(value)?;
         } else {
-            self.current_chunk().write(
-                OpCode::Load(Literal::Nil),
-                return_statement.key_word.position,
-            );
+            self.load_return_value(return_statement.key_word.position);
         }
 
         self.current_chunk()
@@ -698,6 +981,277 @@ impl StmtVisitor<(), LoxError> for Convertor {
         &mut self,
         class_statement: &crate::rlox::stmt::ClassStatement,
     ) -> Result<(), LoxError> {
-        todo!()
+        let name = &class_statement.name;
+        match &class_statement.superclass {
+            Some(superclass) => {
+                self.convert_expression(superclass)?;
+                self.current_chunk()
+                    .write(OpCode::Inherit(name.lexeme.clone()), name.position);
+            }
+            None => {
+                self.current_chunk()
+                    .write(OpCode::Class(name.lexeme.clone()), name.position);
+            }
+        }
+
+        for method in &class_statement.methods {
+            if let Statement::FunctionStatement(method) = method {
+                let func_type = if method.name.lexeme.as_str() == "__init__" {
+                    FuncType::Initializer
+                } else {
+                    FuncType::Method
+                };
+                let func = self.convert_function(method, func_type)?;
+                self.current_chunk()
+                    .write(OpCode::Load(func.into()), method.name.position);
+                self.current_chunk().write(
+                    OpCode::Method(method.name.lexeme.clone()),
+                    method.name.position,
+                );
+            }
+        }
+
+        for method in &class_statement.static_methods {
+            if let Statement::FunctionStatement(method) = method {
+                let func = self.convert_function(method, FuncType::StaticMethod)?;
+                self.current_chunk()
+                    .write(OpCode::Load(func.into()), method.name.position);
+                self.current_chunk().write(
+                    OpCode::StaticMethod(method.name.lexeme.clone()),
+                    method.name.position,
+                );
+            }
+        }
+
+        // A static field is evaluated once, while the class is on the stack.
+        for field in &class_statement.static_fields {
+            if let Statement::MultiVarStatement(field) = field {
+                for var in &field.vars {
+                    if let Statement::VarStatement(var) = var {
+                        match &var.initializer {
+                            Some(initializer) => self.convert_expression(initializer)?,
+                            None => {
+                                self.current_chunk()
+                                    .write(OpCode::Load(Literal::Nil), var.name.position);
+                            }
+                        }
+                        self.current_chunk().write(
+                            OpCode::StaticField(var.name.lexeme.clone()),
+                            var.name.position,
+                        );
+                    }
+                }
+            }
+        }
+
+        self.define_named(name)
+    }
+
+    fn visit_comment_statement(
+        &mut self,
+        _comment_statement: &crate::rlox::stmt::CommentStatement,
+    ) -> Result<(), LoxError> {
+        Ok(())
+    }
+}
+
+/// The string a `+` chain of string literals like `"a" + "b" + "c"` adds up
+/// to, so it can be loaded as one constant. Anything else is left to the VM.
+fn fold_string(expression: &Expression) -> Option<String> {
+    match expression {
+        Expression::LiteralExpression(literal) => match &literal.value {
+            Literal::String(string) => Some(string.to_string()),
+            _ => None,
+        },
+        Expression::GroupingExpression(grouping) => fold_string(&grouping.expression),
+        Expression::BinaryExpression(binary) if binary.op.token_type == TokenType::Plus => {
+            Some(fold_string(&binary.left)? + &fold_string(&binary.right)?)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Convertor;
+    use crate::rlox::bytecode_interpreter::environment::MAX_LOCALS;
+    use crate::rlox::bytecode_interpreter::opcode::OpCode;
+    use crate::rlox::error::LoxError;
+    use crate::rlox::lox::testing::{compile, run};
+    use crate::rlox::parser::Parser;
+    use crate::rlox::token::Token;
+    use crate::rlox::types::{Literal, TokenType};
+
+    fn codes(source: &str) -> Vec<OpCode> {
+        let function = compile(source).unwrap();
+        (0..function.chunk.len())
+            .map(|i| function.chunk.get(i).unwrap().clone())
+            .collect()
+    }
+
+    #[test]
+    fn expression_statement_discards_its_value() {
+        let codes = codes("let a = 1; a + 2;");
+        assert!(codes
+            .windows(2)
+            .any(|w| matches!(w, [OpCode::Add, OpCode::Discard])));
+        assert_eq!(run("let a = 1; a + 2; print a;"), Ok("1\n".into()));
+    }
+
+    #[test]
+    fn redeclaring_a_local_in_the_same_scope_is_an_error() {
+        let err = run("{ let x = 1; let x = 2; }").unwrap_err();
+        assert!(err.starts_with("Already a variable with this name in this scope"));
+        assert_eq!(
+            run("{ let x = 1; { let x = 2; print x; } print x; }"),
+            Ok("2\n1\n".into())
+        );
+    }
+
+    #[test]
+    fn function_returning_on_one_path_returns_nil_on_the_other() {
+        let source = "func f(x) { if (x) return 1; } print f(true); print f(false);";
+        assert_eq!(run(source), Ok("1\nnil\n".into()));
+        assert_eq!(run("func g() {} print g();"), Ok("nil\n".into()));
+    }
+
+    #[test]
+    fn mutually_recursive_functions_in_either_order() {
+        let even_first = "
+            func is_even(n) { if (n == 0) return true; return is_odd(n - 1); }
+            func is_odd(n) { if (n == 0) return false; return is_even(n - 1); }
+            print is_even(10); print is_odd(7);
+        ";
+        assert_eq!(run(even_first), Ok("true\ntrue\n".into()));
+        let odd_first = "
+            print is_even(10); print is_odd(7);
+            func is_odd(n) { if (n == 0) return false; return is_even(n - 1); }
+            func is_even(n) { if (n == 0) return true; return is_odd(n - 1); }
+        ";
+        assert_eq!(run(odd_first), Ok("true\ntrue\n".into()));
+    }
+
+    #[test]
+    fn later_declarations_shadow_earlier_ones() {
+        let source = "let f = 1; func f() { return 2; } print f();";
+        assert_eq!(run(source), Ok("2\n".into()));
+        let source = "func f() { return 2; } let f = 1; print f;";
+        assert_eq!(run(source), Ok("1\n".into()));
+        let source = "func f() { return 1; } func f() { return 2; } print f();";
+        assert_eq!(run(source), Ok("2\n".into()));
+    }
+
+    #[test]
+    fn if_without_else_keeps_the_locals_around_it() {
+        let source = "{ let a = 1; let b = 2; if (true) print 0; print a; print b; }";
+        assert_eq!(run(source), Ok("0\n1\n2\n".into()));
+        let source = "{ let a = 1; if (false) print 0; print a; }";
+        assert_eq!(run(source), Ok("1\n".into()));
+    }
+
+    #[test]
+    fn string_concatenation_chain_folds_into_one_constant() {
+        let codes = codes("print \"a\" + \"b\" + \"c\";");
+        let loads = codes
+            .iter()
+            .filter_map(|code| match code {
+                OpCode::Load(Literal::String(string)) => Some(string.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(loads, vec!["abc"]);
+        assert!(!codes.iter().any(|code| matches!(code, OpCode::Add)));
+        assert_eq!(run("print \"a\" + \"b\" + \"c\";"), Ok("abc\n".into()));
+    }
+
+    #[test]
+    fn too_many_locals_is_a_compile_error() {
+        // `func f() { let v0; let v1; ... }`, built as tokens because
+        // scanning that much source takes too long
+        let function_with_locals = |count: usize| {
+            let token = |token_type, lexeme: &str| {
+                Token::new(token_type, lexeme.to_string(), (1, 0), (1, 0))
+            };
+            let mut tokens = vec![
+                token(TokenType::Func, "func"),
+                token(TokenType::Identifier, "f"),
+                token(TokenType::LeftParen, "("),
+                token(TokenType::RightParen, ")"),
+                token(TokenType::LeftBrace, "{"),
+            ];
+            for i in 0..count {
+                tokens.push(token(TokenType::Let, "let"));
+                tokens.push(token(TokenType::Identifier, &format!("v{i}")));
+                tokens.push(token(TokenType::Semicolon, ";"));
+            }
+            tokens.push(token(TokenType::RightBrace, "}"));
+            tokens.push(token(TokenType::Eof, ""));
+            let statements = Parser::new(tokens).parse().unwrap();
+            Convertor::default().convert(&statements)
+        };
+
+        // slot 0 of a function holds the function itself
+        match function_with_locals(MAX_LOCALS) {
+            Err(LoxError::ParseError { lexeme, msg, .. }) => {
+                assert_eq!(msg, "Too many local variables in function.");
+                assert_eq!(*lexeme, format!("v{}", MAX_LOCALS - 1));
+            }
+            _ => panic!("expected a compile error"),
+        }
+        assert!(function_with_locals(MAX_LOCALS - 1).is_ok());
+    }
+
+    #[test]
+    fn redeclaration_error_points_at_both_declarations() {
+        let errors = compile("{\n  let x = 1;\n  let x = 2;\n}").unwrap_err();
+        match &errors[0] {
+            LoxError::RuntimeError { position, msg, .. } => {
+                assert_eq!(*position, (3, 6));
+                assert!(msg.ends_with("previously declared at [2,6]."), "{msg}");
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn error_in_a_callee_fails_the_call() {
+        // `self` in a lambda isn't the method's, so it can't be compiled
+        let source = "class A {\n  m() { return func() { return self(); }; }\n}";
+        let errors = compile(source).unwrap_err();
+        match &errors[0] {
+            LoxError::RuntimeError { position, msg, .. } => {
+                assert_eq!(*position, (2, 31));
+                assert_eq!(msg, "Keyword `self` can only be used directly in a method.");
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn every_break_of_a_while_loop_lands_just_past_it() {
+        let source = "let i = 0;\nwhile (true) {\n  if (i == 2) break;\n  if (i > 5) break;\n  i = i + 1;\n}\nprint i;";
+        let function = compile(source).unwrap();
+        let chunk = &function.chunk;
+        let codes = codes(source);
+
+        // `print i` starts by reading `i` on line 7
+        let after_loop = (0..chunk.len())
+            .find(|i| chunk.get_position(*i).unwrap().0 == 7)
+            .unwrap();
+        assert!(matches!(&codes[after_loop], OpCode::GetGlobal(name) if **name == "i"));
+        assert!(matches!(&codes[after_loop - 1], OpCode::Pop));
+
+        let breaks = (0..chunk.len())
+            .filter(|i| matches!(codes[*i], OpCode::Jump(_)))
+            .filter(|i| matches!(chunk.get_position(*i), Some((3 | 4, _))))
+            .collect::<Vec<_>>();
+        assert_eq!(breaks.len(), 2);
+        for i in breaks {
+            let OpCode::Jump(offset) = codes[i] else {
+                unreachable!()
+            };
+            assert_eq!(i + offset + 1, after_loop);
+        }
+        assert_eq!(run(source), Ok("2\n".into()));
     }
 }
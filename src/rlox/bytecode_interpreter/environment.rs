@@ -2,11 +2,17 @@ use std::{collections::HashMap, rc::Rc};
 
 use super::opcode::OpCode;
 
+/// The most locals a function can have, its own slot and parameters
+/// included. Slots are `usize`, so this isn't an encoding limit, but it
+/// turns a runaway generated function into a compile error rather than a
+/// frame too big to be useful.
+pub const MAX_LOCALS: usize = u16::MAX as usize + 1;
+
 #[derive(Default, Debug)]
 pub struct Scopes {
-    pub variables: Vec<(Rc<String>, usize)>,
+    /// Each local's name and scope depth, and where it was declared.
+    pub variables: Vec<(Rc<String>, usize, (usize, usize))>,
     pub var_map: HashMap<Rc<String>, Vec<usize>>,
-    pub count: usize,
     pub depth: usize,
 }
 
@@ -30,7 +36,7 @@ impl Scopes {
 
     pub fn will_delete_var_by_depth(&mut self, depth: usize) -> Vec<OpCode> {
         let mut codes = vec![];
-        let mut len = self.variables.iter().filter(|(_, d)| depth < *d).count();
+        let mut len = self.variables.iter().filter(|(_, d, _)| depth < *d).count();
         while len > 0 {
             codes.push(OpCode::Pop);
             len -= 1;
@@ -39,31 +45,38 @@ impl Scopes {
         codes
     }
 
-    pub fn define_variable(&mut self, name: Rc<String>, depth: usize) -> Result<(), ()> {
-        if !self.is_variable_at_same_depth(&name, depth) {
-            let index = self.variables.len();
-            self.variables.push((name.clone(), depth));
-            self.var_map
-                .entry(name)
-                .and_modify(|v| v.push(index))
-                .or_insert_with(|| vec![index]);
-            return Ok(());
-        }
-        Err(())
+    pub fn is_full(&self) -> bool {
+        self.variables.len() >= MAX_LOCALS
     }
 
-    fn is_variable_at_same_depth(&self, name: &Rc<String>, depth: usize) -> bool {
-        if let Some(index) = self.var_map.get(name) {
-            if !index.is_empty() {
-                for i in index.iter().rev() {
-                    if self.variables[*i].1 == depth {
-                        return true;
-                    }
-                }
-            }
-            return false;
+    /// Fails with the position of the earlier declaration when `name` is
+    /// already declared at the same depth.
+    pub fn define_variable(
+        &mut self,
+        name: Rc<String>,
+        depth: usize,
+        position: (usize, usize),
+    ) -> Result<(), (usize, usize)> {
+        if let Some(previous) = self.declared_at_same_depth(&name, depth) {
+            return Err(previous);
         }
-        false
+        let index = self.variables.len();
+        self.variables.push((name.clone(), depth, position));
+        self.var_map
+            .entry(name)
+            .and_modify(|v| v.push(index))
+            .or_insert_with(|| vec![index]);
+        Ok(())
+    }
+
+    fn declared_at_same_depth(&self, name: &Rc<String>, depth: usize) -> Option<(usize, usize)> {
+        self.var_map
+            .get(name)?
+            .iter()
+            .rev()
+            .map(|i| &self.variables[*i])
+            .find(|variable| variable.1 == depth)
+            .map(|variable| variable.2)
     }
 
     pub fn find_variable(&self, name: Rc<String>) -> Result<usize, ()> {
@@ -1,5 +1,6 @@
 pub mod chunk;
 pub mod convertor;
 mod environment;
+pub mod native;
 mod opcode;
 pub mod vm;
@@ -0,0 +1,629 @@
+use std::{cell::RefCell, fs, path::Path, rc::Rc};
+
+use crate::rlox::types::{Arity, Array, Literal, Map, NativeFunction};
+
+use super::vm::VirtualMachine;
+
+pub fn natives() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("sb_new", 0, sb_new),
+        NativeFunction::variadic("sb_push", 2, sb_push),
+        NativeFunction::new("sb_build", 1, sb_build),
+        NativeFunction::new("exit", 1, exit),
+        NativeFunction::new("assert_throws", 1, assert_throws),
+        NativeFunction::new("freeze", 1, freeze),
+        NativeFunction::new("abs_path", 1, abs_path),
+        NativeFunction::new("read_file", 1, read_file),
+        NativeFunction::new("write_file", 2, write_file),
+        NativeFunction::new("file_exists", 1, file_exists),
+        NativeFunction::new("random", 0, random),
+        NativeFunction::new("clock", 0, clock),
+        NativeFunction::new("len", 1, len),
+        NativeFunction::variadic("range", 1, range),
+        NativeFunction::new("type", 1, type_of),
+        NativeFunction::new("equals", 2, equals),
+        NativeFunction::new("str", 1, str),
+        NativeFunction::new("num", 1, num),
+        NativeFunction::new("sqrt", 1, sqrt),
+        NativeFunction::new("to_upper", 1, to_upper),
+        NativeFunction::new("to_lower", 1, to_lower),
+        NativeFunction::new("trim", 1, trim),
+        NativeFunction::new("contains", 2, contains),
+        NativeFunction::new("index_of", 2, index_of),
+        NativeFunction::new("replace", 3, replace),
+    ]
+}
+
+// A string builder accumulates text in place, so building a long string in
+// a loop doesn't copy the whole string on every step like `s = s + x` does.
+fn sb_new(_: &mut VirtualMachine, _: &[Literal]) -> Result<Literal, String> {
+    Ok(Literal::StringBuilder(Default::default()))
+}
+
+// `sb_push(builder, a, b, ...)` appends each value in turn. It takes any
+// number of values rather than exactly one, so a piece made of several parts,
+// like `sb_push(sb, key, ": ", value, "\n")`, is one call instead of four.
+fn sb_push(_: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    let builder = get_string_builder(&args[0])?;
+    for value in &args[1..] {
+        match value {
+            Literal::String(string) => builder.borrow_mut().push_str(string),
+            value => builder.borrow_mut().push_str(&value.to_string()),
+        }
+    }
+    Ok(Literal::Nil)
+}
+
+fn sb_build(_: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    let builder = get_string_builder(&args[0])?;
+    let string = builder.borrow().clone();
+    Ok(Rc::new(string).into())
+}
+
+// Killing the process would take the whole session with it in the REPL,
+// where Ctrl-D already ends it, so `exit` is only available to scripts.
+fn exit(vm: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    if std::env::var("RLOX_RUN_MODE").unwrap() == "R" {
+        return Err("`exit` is not available in the REPL, use Ctrl-D instead.".into());
+    }
+
+    let code = exit_code(&args[0])?;
+    vm.shutdown();
+    std::process::exit(code)
+}
+
+fn exit_code(value: &Literal) -> Result<i32, String> {
+    match value {
+        Literal::Number(code)
+            if code.fract() == 0.0 && (i32::MIN as f64..=i32::MAX as f64).contains(code) =>
+        {
+            Ok(*code as i32)
+        }
+        _ => Err(format!("Expect an integer exit code but got `{}`.", value)),
+    }
+}
+
+// Only a runtime error raised while the function runs counts as thrown, so
+// anything that can't be called without arguments is rejected up front
+// rather than "throwing" when the call is attempted.
+fn assert_throws(vm: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    let arity = match &args[0] {
+        Literal::Function(function) => Arity::Exact(function.arity),
+        Literal::BoundMethod(bound) => Arity::Exact(bound.method.arity),
+        Literal::Native(native) => native.arity,
+        value => {
+            return Err(format!(
+                "`assert_throws` expects a function, but got `{}`.",
+                value
+            ))
+        }
+    };
+    if !arity.accepts(0) {
+        return Err(format!(
+            "`assert_throws` expects a function without parameters, but got `{}`.",
+            args[0]
+        ));
+    }
+
+    match vm.call(&args[0], &[]) {
+        Ok(_) => Err("Expected a runtime error but none occurred.".into()),
+        Err(_) => Ok(Literal::Nil),
+    }
+}
+
+// Freezing only stops fields from being set, so methods can still be
+// called, and a frozen instance is returned for `let p = freeze(P());`.
+fn freeze(_: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    match &args[0] {
+        Literal::Instance(instance) => {
+            instance.frozen.set(true);
+            Ok(args[0].clone())
+        }
+        value => Err(format!("Expect an instance but got `{}`.", value)),
+    }
+}
+
+// Pass `--seed N` to get the same numbers on every run.
+fn random(vm: &mut VirtualMachine, _: &[Literal]) -> Result<Literal, String> {
+    Ok(vm.next_random().into())
+}
+
+// Strings count chars rather than bytes, like the columns in messages do.
+fn len(_: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    let len = match &args[0] {
+        Literal::String(string) => string.chars().count(),
+        Literal::StringBuilder(builder) => builder.borrow().chars().count(),
+        value => Array::try_from(value)
+            .map(|array| array.borrow().len())
+            .or_else(|_| Map::try_from(value).map(|map| map.borrow().len()))
+            .map_err(|_| format!("`len` expects a string, array or map, but got `{}`.", value))?,
+    };
+    Ok((len as f64).into())
+}
+
+/// The most elements `range` builds an array of.
+const MAX_RANGE_LEN: usize = 10_000_000;
+
+// `range(end)`, `range(start, end)` or `range(start, end, step)` gives the
+// integers from `start`, which is 0 by default, up to but not including
+// `end`. A negative step counts down, and a range that is empty in the
+// direction of the step gives `[]`.
+fn range(_: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    let args = args
+        .iter()
+        .map(get_integer)
+        .collect::<Result<Vec<_>, _>>()?;
+    let (start, end, step) = match args[..] {
+        [end] => (0.0, end, 1.0),
+        [start, end] => (start, end, 1.0),
+        [start, end, step] => (start, end, step),
+        _ => {
+            return Err(format!(
+                "Expect 1 to 3 arguments but got {} when calling native fn `range`.",
+                args.len()
+            ))
+        }
+    };
+    if step == 0.0 {
+        return Err("`range` step cannot be 0.".into());
+    }
+
+    let len = ((end - start) / step).ceil().max(0.0);
+    if len > MAX_RANGE_LEN as f64 {
+        return Err(format!(
+            "`range` would have {} elements, more than the {} allowed.",
+            len, MAX_RANGE_LEN
+        ));
+    }
+    let elements = (0..len as usize)
+        .map(|i| Literal::Number(start + i as f64 * step))
+        .collect();
+    Ok(Literal::Array(Rc::new(RefCell::new(elements))))
+}
+
+// `==` tells whether two arrays, maps or instances are the same one, and
+// `equals` whether they hold equal values.
+fn equals(_: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    Ok(args[0].is_deep_equal(&args[1]).into())
+}
+
+fn type_of(_: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    Ok(Rc::new(args[0].type_name().to_string()).into())
+}
+
+fn str(_: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    match &args[0] {
+        Literal::String(string) => Ok(Literal::String(string.clone())),
+        value => Ok(Rc::new(value.to_string()).into()),
+    }
+}
+
+// Surrounding whitespace is ignored, so a line read from a file converts
+// as it is. Rust also parses words like `inf` and `NaN`, which aren't Lox
+// numbers, so only finite results are taken.
+fn num(_: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    match &args[0] {
+        Literal::Number(num) => Ok(Literal::Number(*num)),
+        Literal::String(string) => string
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|num| num.is_finite())
+            .map(Literal::Number)
+            .ok_or_else(|| format!("Can't convert \"{}\" to a number.", string)),
+        value => Err(format!(
+            "`num` expects a number or a string, but got `{}`.",
+            value
+        )),
+    }
+}
+
+// Like the arithmetic operators, `sqrt` never makes a NaN, so the root of a
+// negative number is an error.
+fn sqrt(_: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    match &args[0] {
+        Literal::Number(num) if *num >= 0.0 => Ok(num.sqrt().into()),
+        value => Err(format!(
+            "`sqrt` expects a non-negative number, but got `{}`.",
+            value
+        )),
+    }
+}
+
+// Case mapping follows Unicode, so `to_upper("straße")` is "STRASSE".
+fn to_upper(_: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    Ok(Rc::new(get_string(&args[0])?.to_uppercase()).into())
+}
+
+fn to_lower(_: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    Ok(Rc::new(get_string(&args[0])?.to_lowercase()).into())
+}
+
+fn trim(_: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    Ok(Rc::new(get_string(&args[0])?.trim().to_string()).into())
+}
+
+fn contains(_: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    let (haystack, needle) = (get_string(&args[0])?, get_string(&args[1])?);
+    Ok(haystack.contains(needle.as_str()).into())
+}
+
+// The index counts chars, so it can be used with the other natives, and is
+// -1 when `needle` isn't found.
+fn index_of(_: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    let (haystack, needle) = (get_string(&args[0])?, get_string(&args[1])?);
+    let index = haystack
+        .find(needle.as_str())
+        .map_or(-1.0, |byte| haystack[..byte].chars().count() as f64);
+    Ok(index.into())
+}
+
+// Every occurrence is replaced. An empty `from` matches between every two
+// chars, and at both ends, so `replace("ab", "", "-")` is "-a-b-".
+fn replace(_: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    let string = get_string(&args[0])?;
+    let (from, to) = (get_string(&args[1])?, get_string(&args[2])?);
+    Ok(Rc::new(string.replace(from.as_str(), to)).into())
+}
+
+// Seconds since the program started, for timing code from inside Lox.
+fn clock(vm: &mut VirtualMachine, _: &[Literal]) -> Result<Literal, String> {
+    Ok(vm.elapsed().into())
+}
+
+// The file natives need `--allow-io`, so a script can't touch the file
+// system unless asked to. A failed operation isn't a runtime error: it gives
+// `nil`, or `false` for `write_file`, and the script decides what to do.
+fn abs_path(vm: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    let path = get_path(vm, &args[0])?;
+    Ok(fs::canonicalize(path)
+        .map(|path| Rc::new(path.to_string_lossy().into_owned()).into())
+        .unwrap_or(Literal::Nil))
+}
+
+fn read_file(vm: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    let path = get_path(vm, &args[0])?;
+    Ok(fs::read_to_string(path)
+        .map(|contents| Rc::new(contents).into())
+        .unwrap_or(Literal::Nil))
+}
+
+fn write_file(vm: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    let path = get_path(vm, &args[0])?;
+    let written = match &args[1] {
+        Literal::String(contents) => fs::write(path, contents.as_bytes()),
+        value => fs::write(path, value.to_string()),
+    };
+    Ok(Literal::Bool(written.is_ok()))
+}
+
+fn file_exists(vm: &mut VirtualMachine, args: &[Literal]) -> Result<Literal, String> {
+    let path = get_path(vm, &args[0])?;
+    Ok(Literal::Bool(path.exists()))
+}
+
+fn get_path<'a>(vm: &VirtualMachine, value: &'a Literal) -> Result<&'a Path, String> {
+    if !vm.allow_io() {
+        return Err("File access is disabled, run with `--allow-io` to enable it.".into());
+    }
+
+    match value {
+        Literal::String(path) => Ok(Path::new(path.as_str())),
+        _ => Err(format!("Expect a path string but got `{}`.", value)),
+    }
+}
+
+fn get_integer(value: &Literal) -> Result<f64, String> {
+    match value {
+        Literal::Number(num) if num.fract() == 0.0 => Ok(*num),
+        _ => Err(format!("Expect an integer but got `{}`.", value)),
+    }
+}
+
+fn get_string(value: &Literal) -> Result<&Rc<String>, String> {
+    match value {
+        Literal::String(string) => Ok(string),
+        _ => Err(format!("Expect a string but got `{}`.", value)),
+    }
+}
+
+fn get_string_builder(value: &Literal) -> Result<&Rc<RefCell<String>>, String> {
+    match value {
+        Literal::StringBuilder(builder) => Ok(builder),
+        _ => Err(format!("Expect a string builder but got `{}`.", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::exit_code;
+    use crate::rlox::error::LoxError;
+    use crate::rlox::lox::testing::{compile, message, run, vm};
+    use crate::rlox::lox::Lox;
+    use crate::rlox::types::Literal;
+    use std::process::Command;
+
+    #[test]
+    fn string_builder_builds_a_long_string() {
+        let source = r#"
+            let sb = sb_new();
+            let i = 0;
+            while (i < 10000) { sb_push(sb, i % 10); i = i + 1; }
+            print sb_build(sb);
+        "#;
+        assert_eq!(run(source), Ok("0123456789".repeat(1000) + "\n"));
+    }
+
+    // Builds the same string from 100000 pieces with `+` and with a builder,
+    // run with `cargo test --release concatenation -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn concatenation_with_plus_against_a_string_builder() {
+        let time = |body: &str, result: &str| {
+            let source = format!(
+                "let s = \"\"; let sb = sb_new(); let i = 0; while (i < 100000) {{ {body} i += 1; }} print len({result});"
+            );
+            let (mut vm, out) = vm();
+            let function = compile(&source).unwrap();
+            let start = std::time::Instant::now();
+            vm.interpret(function).unwrap();
+            assert_eq!(out.take(), "100000\n");
+            start.elapsed()
+        };
+
+        let plus = time("s = s + \"a\";", "s");
+        let builder = time("sb_push(sb, \"a\");", "sb_build(sb)");
+        println!("+: {:?}, string builder: {:?}", plus, builder);
+    }
+
+    #[test]
+    fn exit_requests_an_integer_code() {
+        assert_eq!(exit_code(&2.0.into()), Ok(2));
+        assert_eq!(exit_code(&(-1.0).into()), Ok(-1));
+        assert!(exit_code(&2.5.into()).is_err());
+        assert!(exit_code(&Literal::Nil).is_err());
+        assert!(run("exit(2.5);").unwrap_err().contains("integer exit code"));
+    }
+
+    // `exit` ends the process, so the program runs in a copy of this test
+    // binary that only runs `exiting_child`.
+    #[test]
+    fn exit_shuts_down_like_the_end_of_the_program() {
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "--ignored", "--nocapture"])
+            .arg("rlox::bytecode_interpreter::native::tests::exiting_child")
+            .env("RLOX_EXITING_CHILD", "1")
+            .env("RLOX_PROFILE", "1")
+            .env("RLOX_COVERAGE", "1")
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        assert_eq!(output.status.code(), Some(3), "{stdout}");
+        // the harness prints the test name in front of the program's output
+        let lines = stdout.lines().collect::<Vec<_>>();
+        let printed = lines.iter().position(|line| line.ends_with(" 1")).unwrap();
+        assert!(lines[printed + 1].contains("[PROFILE]"), "{stdout}");
+        assert!(stdout.contains("line 3 not executed"), "{stdout}");
+    }
+
+    #[test]
+    #[ignore]
+    fn exiting_child() {
+        if std::env::var("RLOX_EXITING_CHILD").is_ok() {
+            Lox::run_source("print 1;\nexit(3);\nprint 2;".into()).unwrap();
+        }
+    }
+
+    #[test]
+    fn assert_throws_expects_a_runtime_error() {
+        let source = "assert_throws(func() { return 1 / 0; }); print 1;";
+        assert_eq!(run(source), Ok("1\n".into()));
+        let err = run("assert_throws(func() { return 1; });").unwrap_err();
+        assert!(err.contains("Expected a runtime error but none occurred."));
+
+        let source = "class A { bad() { return nil + 1; } } assert_throws(A().bad); print 2;";
+        assert_eq!(run(source), Ok("2\n".into()));
+    }
+
+    #[test]
+    fn assert_throws_rejects_what_it_cannot_call() {
+        let err = run("assert_throws(func(x) {});").unwrap_err();
+        assert_eq!(
+            err,
+            "`assert_throws` expects a function without parameters, but got `<lambda/1>`."
+        );
+        let err = run("assert_throws(nil);").unwrap_err();
+        assert_eq!(err, "`assert_throws` expects a function, but got `nil`.");
+        let err = run("assert_throws(1);").unwrap_err();
+        assert_eq!(err, "`assert_throws` expects a function, but got `1`.");
+    }
+
+    #[test]
+    fn file_natives_round_trip_through_a_temp_file() {
+        let run_with_io = |source: &str, allow_io: bool| {
+            let (mut vm, out) = vm();
+            vm.set_allow_io(allow_io);
+            let function = compile(source).unwrap();
+            vm.interpret(function).map(|_| out.take())
+        };
+
+        let err = run_with_io("read_file(\"x\");", false).unwrap_err();
+        assert!(message(&err).starts_with("File access is disabled"));
+
+        let path = std::env::temp_dir().join(format!("rlox-test-{}.txt", std::process::id()));
+        let source = format!(
+            r#"
+                let path = "{}";
+                print write_file(path, "line\n"), file_exists(path);
+                print read_file(path) == "line\n";
+            "#,
+            path.display()
+        );
+        assert_eq!(run_with_io(&source, true).unwrap(), "true true\ntrue\n");
+        std::fs::remove_file(&path).unwrap();
+
+        let missing = format!(
+            "print read_file(\"{}\"), file_exists(\"{0}\");",
+            path.display()
+        );
+        assert_eq!(run_with_io(&missing, true).unwrap(), "nil false\n");
+    }
+
+    #[test]
+    fn native_arity_is_checked_for_fixed_and_variadic_natives() {
+        let err = run("sb_build(1, 2);").unwrap_err();
+        assert_eq!(
+            err,
+            "Expect 1 arguments but got 2 when calling native fn `sb_build`."
+        );
+        let err = run("sb_push(sb_new());").unwrap_err();
+        assert!(err.starts_with("Expect at least 2 arguments but got 1"));
+
+        let source = "let sb = sb_new(); sb_push(sb, 1); sb_push(sb, 2, 3, 4); print sb_build(sb);";
+        assert_eq!(run(source), Ok("1234\n".into()));
+    }
+
+    #[test]
+    fn clock_counts_seconds_forward() {
+        let source = "let a = clock(); let b = clock(); print a >= 0, b >= a, b - a < 1;";
+        assert_eq!(run(source), Ok("true true true\n".into()));
+    }
+
+    #[test]
+    fn len_counts_chars_and_entries() {
+        let source = r#"print len("héllo"), len([1, 2]), len({"a": 1}), len(sb_new());"#;
+        assert_eq!(run(source), Ok("5 2 1 0\n".into()));
+
+        let (mut vm, _) = vm();
+        match vm.interpret(compile("\nlen(true);").unwrap()) {
+            Err(LoxError::RuntimeError { position, msg, .. }) => {
+                assert_eq!(position, (2, 8));
+                assert_eq!(msg, "`len` expects a string, array or map, but got `true`.");
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn type_names_every_kind_of_value() {
+        let source = r#"
+            class A { m() {} }
+            print type(1), type("s"), type(true), type(nil), type([]), type({});
+            print type(clock), type(func() {}), type(A), type(A()), type(A().m), type(sb_new());
+        "#;
+        assert_eq!(
+            run(source),
+            Ok("number string bool nil array map\nfunction function class instance function string_builder\n".into())
+        );
+    }
+
+    #[test]
+    fn str_and_num_convert_between_strings_and_numbers() {
+        let source = r#"print "count: " + str(3), str(nil), num(" 1.5 ") + 1, num(2);"#;
+        assert_eq!(run(source), Ok("count: 3 nil 2.5 2\n".into()));
+        let err = run(r#"num("abc");"#).unwrap_err();
+        assert_eq!(err, "Can't convert \"abc\" to a number.");
+        assert!(run(r#"num("inf");"#).is_err());
+        let err = run("num(true);").unwrap_err();
+        assert_eq!(err, "`num` expects a number or a string, but got `true`.");
+    }
+
+    #[test]
+    fn range_counts_up_or_down_by_its_step() {
+        assert_eq!(
+            run("print range(3), range(0);"),
+            Ok("[0, 1, 2] []\n".into())
+        );
+        assert_eq!(
+            run("print range(2, 5), range(5, 2);"),
+            Ok("[2, 3, 4] []\n".into())
+        );
+        let source = "print range(0, 10, 3), range(5, 0, -2), range(-1, -3, -1);";
+        assert_eq!(run(source), Ok("[0, 3, 6, 9] [5, 3, 1] [-1, -2]\n".into()));
+
+        assert_eq!(
+            run("range(0, 5, 0);").unwrap_err(),
+            "`range` step cannot be 0."
+        );
+        assert_eq!(
+            run("range(1.5);").unwrap_err(),
+            "Expect an integer but got `1.5`."
+        );
+        assert_eq!(
+            run("range(1, 2, 3, 4);").unwrap_err(),
+            "Expect 1 to 3 arguments but got 4 when calling native fn `range`."
+        );
+        let err = run("range(100000000);").unwrap_err();
+        assert!(err.starts_with("`range` would have 100000000 elements"));
+    }
+
+    #[test]
+    fn equals_compares_structure_where_eq_compares_identity() {
+        let source =
+            "let a = [1, [2, 3]]; let b = [1, [2, 3]]; print a == b, equals(a, b), a == a;";
+        assert_eq!(run(source), Ok("false true true\n".into()));
+        let source = r#"print equals([1, 2], [1, 2, 3]), equals([1, "2"], [1, 2]), equals(1, 1);"#;
+        assert_eq!(run(source), Ok("false false true\n".into()));
+
+        let source =
+            r#"print equals({"a": [1], 2: nil}, {2: nil, "a": [1]}), equals({"a": 1}, {"b": 1});"#;
+        assert_eq!(run(source), Ok("true false\n".into()));
+
+        let source = "
+            class P { __init__(x) { self.x = x; } }
+            class Q { __init__(x) { self.x = x; } }
+            print P(1) == P(1), equals(P(1), P(1)), equals(P(1), P(2)), equals(P(1), Q(1));
+        ";
+        assert_eq!(run(source), Ok("false true false false\n".into()));
+    }
+
+    #[test]
+    fn equals_terminates_on_cycles() {
+        let source = "
+            let a = [1, nil]; a[1] = a;
+            let b = [1, nil]; b[1] = b;
+            let c = [1, [1, nil]]; c[1][1] = c;
+            let d = [2, nil]; d[1] = d;
+            print equals(a, b), equals(a, c), equals(a, d);
+        ";
+        assert_eq!(run(source), Ok("true true false\n".into()));
+        let source = r#"let m = {}; m["m"] = m; let n = {}; n["m"] = n; print equals(m, n);"#;
+        assert_eq!(run(source), Ok("true\n".into()));
+    }
+
+    #[test]
+    fn sqrt_takes_non_negative_numbers() {
+        assert_eq!(
+            run("print sqrt(16), sqrt(2.25), sqrt(0);"),
+            Ok("4 1.5 0\n".into())
+        );
+        let err = run("sqrt(-0.5);").unwrap_err();
+        assert_eq!(err, "`sqrt` expects a non-negative number, but got `-0.5`.");
+        assert!(run(r#"sqrt("4");"#).is_err());
+    }
+
+    #[test]
+    fn case_mapping_and_trim_follow_unicode() {
+        let source = r#"print to_upper("straße"), to_lower("ÀÉ"), "[" + trim(" \t hi \n") + "]";"#;
+        assert_eq!(run(source), Ok("STRASSE àé [hi]\n".into()));
+        for call in ["to_upper(1);", "to_lower(nil);", "trim([]);"] {
+            assert!(run(call)
+                .unwrap_err()
+                .starts_with("Expect a string but got"));
+        }
+    }
+
+    #[test]
+    fn contains_index_of_and_replace_search_strings() {
+        let source = r#"
+            print contains("hello", "ell"), contains("hello", "xyz");
+            print index_of("héllo", "l"), index_of("abc", "z");
+            print replace("a-b-c", "-", "+"), replace("abc", "", "-");
+        "#;
+        assert_eq!(run(source), Ok("true false\n2 -1\na+b+c -a-b-c-\n".into()));
+        assert!(run(r#"contains(1, "a");"#)
+            .unwrap_err()
+            .starts_with("Expect a string"));
+    }
+}
@@ -17,8 +17,10 @@ pub enum OpCode {
     Less,
     Greater,
 
-    Print,
+    Print(usize),
+    Assert(Rc<String>),
     Pop,
+    Discard,
     DefineGlobal(Rc<String>),
     GetGlobal(Rc<String>),
     SetGlobal(Rc<String>),
@@ -31,6 +33,20 @@ pub enum OpCode {
 
     Call(usize),
 
+    Class(Rc<String>),
+    Inherit(Rc<String>),
+    Method(Rc<String>),
+    StaticMethod(Rc<String>),
+    StaticField(Rc<String>),
+    GetProperty(Rc<String>),
+    GetSuper(Rc<String>),
+    SetProperty(Rc<String>),
+
+    BuildArray(usize),
+    BuildMap(usize),
+    Index,
+    SetIndex,
+
     AddIGlobal(Rc<String>),
     SubIGlobal(Rc<String>),
     MulIGlobal(Rc<String>),
@@ -41,6 +57,13 @@ pub enum OpCode {
     MulILocal(usize),
     DivILocal(usize),
     ModILocal(usize),
+
+    AddLocal(usize),
+    SubLocal(usize),
+    MulLocal(usize),
+    DivLocal(usize),
+    LessLocal(usize),
+    GreaterLocal(usize),
 }
 
 impl From<Literal> for OpCode {
@@ -49,6 +72,67 @@ impl From<Literal> for OpCode {
     }
 }
 
+impl OpCode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpCode::Return => "RETURN",
+            OpCode::Load(_) => "LOAD",
+            OpCode::Negate => "NEGATE",
+            OpCode::Add => "ADD",
+            OpCode::Sub => "SUB",
+            OpCode::Mul => "MUL",
+            OpCode::Div => "DIV",
+            OpCode::Mod => "MOD",
+            OpCode::Not => "NOT",
+            OpCode::Eq => "EQUAL",
+            OpCode::Less => "LESS",
+            OpCode::Greater => "GREATER",
+            OpCode::Print(_) => "PRINT",
+            OpCode::Assert(_) => "ASSERT",
+            OpCode::Pop => "POP",
+            OpCode::Discard => "DISCARD",
+            OpCode::DefineGlobal(_) => "DEFINE_GLOBAL",
+            OpCode::GetGlobal(_) => "GET_GLOBAL",
+            OpCode::SetGlobal(_) => "SET_GLOBAL",
+            OpCode::GetLocal(_) => "GET_LOCAL",
+            OpCode::SetLocal(_) => "SET_LOCAL",
+            OpCode::Jump(_) => "JUMP",
+            OpCode::JumpForward(_) => "JUMP_FORWARD",
+            OpCode::JumpIfTrue(_) => "JUMP_IF_TRUE",
+            OpCode::JumpIfFalse(_) => "JUMP_IF_FALSE",
+            OpCode::Call(_) => "CALL",
+            OpCode::Class(_) => "CLASS",
+            OpCode::Inherit(_) => "INHERIT",
+            OpCode::Method(_) => "METHOD",
+            OpCode::StaticMethod(_) => "STATIC_METHOD",
+            OpCode::StaticField(_) => "STATIC_FIELD",
+            OpCode::GetProperty(_) => "GET_PROPERTY",
+            OpCode::GetSuper(_) => "GET_SUPER",
+            OpCode::SetProperty(_) => "SET_PROPERTY",
+            OpCode::BuildArray(_) => "BUILD_ARRAY",
+            OpCode::BuildMap(_) => "BUILD_MAP",
+            OpCode::Index => "INDEX",
+            OpCode::SetIndex => "SET_INDEX",
+            OpCode::AddIGlobal(_) => "ADD_I_GLOBAL",
+            OpCode::SubIGlobal(_) => "SUB_I_GLOBAL",
+            OpCode::MulIGlobal(_) => "MUL_I_GLOBAL",
+            OpCode::DivIGlobal(_) => "DIV_I_GLOBAL",
+            OpCode::ModIGlobal(_) => "MOD_I_GLOBAL",
+            OpCode::AddILocal(_) => "ADD_I_LOCAL",
+            OpCode::SubILocal(_) => "SUB_I_LOCAL",
+            OpCode::MulILocal(_) => "MUL_I_LOCAL",
+            OpCode::DivILocal(_) => "DIV_I_LOCAL",
+            OpCode::ModILocal(_) => "MOD_I_LOCAL",
+            OpCode::AddLocal(_) => "ADD_LOCAL",
+            OpCode::SubLocal(_) => "SUB_LOCAL",
+            OpCode::MulLocal(_) => "MUL_LOCAL",
+            OpCode::DivLocal(_) => "DIV_LOCAL",
+            OpCode::LessLocal(_) => "LESS_LOCAL",
+            OpCode::GreaterLocal(_) => "GREATER_LOCAL",
+        }
+    }
+}
+
 impl Display for OpCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -69,8 +153,10 @@ impl Display for OpCode {
             OpCode::Eq => write!(f, "{:<24}", "EQUAL"),
             OpCode::Less => write!(f, "{:<24}", "LESS"),
             OpCode::Greater => write!(f, "{:<24}", "GREATER"),
-            OpCode::Print => write!(f, "{:<24}", "PRINT"),
+            OpCode::Print(v) => write!(f, "{:<15} {:>8}", "PRINT", v),
+            OpCode::Assert(v) => write!(f, "{:<15} {:>8}", "ASSERT", v),
             OpCode::Pop => write!(f, "{:<24}", "POP"),
+            OpCode::Discard => write!(f, "{:<24}", "DISCARD"),
             OpCode::DefineGlobal(v) => write!(f, "{:<15} {:>8}", "DEFINE_GLOBAL", v),
             OpCode::GetGlobal(v) => write!(f, "{:<15} {:>8}", "GET_GLOBAL", v),
             OpCode::SetGlobal(v) => write!(f, "{:<15} {:>8}", "SET_GLOBAL", v),
@@ -81,6 +167,18 @@ impl Display for OpCode {
             OpCode::JumpIfTrue(v) => write!(f, "{:<15} {:>8}", "JUMP_IF_TRUE", v),
             OpCode::JumpIfFalse(v) => write!(f, "{:<15} {:>8}", "JUMP_IF_FALSE", v),
             OpCode::Call(v) => write!(f, "{:<15} {:>8}", "CALL", v),
+            OpCode::Class(v) => write!(f, "{:<15} {:>8}", "CLASS", v),
+            OpCode::Inherit(v) => write!(f, "{:<15} {:>8}", "INHERIT", v),
+            OpCode::Method(v) => write!(f, "{:<15} {:>8}", "METHOD", v),
+            OpCode::StaticMethod(v) => write!(f, "{:<15} {:>8}", "STATIC_METHOD", v),
+            OpCode::StaticField(v) => write!(f, "{:<15} {:>8}", "STATIC_FIELD", v),
+            OpCode::GetProperty(v) => write!(f, "{:<15} {:>8}", "GET_PROPERTY", v),
+            OpCode::GetSuper(v) => write!(f, "{:<15} {:>8}", "GET_SUPER", v),
+            OpCode::SetProperty(v) => write!(f, "{:<15} {:>8}", "SET_PROPERTY", v),
+            OpCode::BuildArray(v) => write!(f, "{:<15} {:>8}", "BUILD_ARRAY", v),
+            OpCode::BuildMap(v) => write!(f, "{:<15} {:>8}", "BUILD_MAP", v),
+            OpCode::Index => write!(f, "{:<24}", "INDEX"),
+            OpCode::SetIndex => write!(f, "{:<24}", "SET_INDEX"),
             OpCode::AddIGlobal(v) => write!(f, "{:<15} {:>8}", "ADD_I_GLOBAL", v),
             OpCode::SubIGlobal(v) => write!(f, "{:<15} {:>8}", "SUB_I_GLOBAL", v),
             OpCode::MulIGlobal(v) => write!(f, "{:<15} {:>8}", "MUL_I_GLOBAL", v),
@@ -91,6 +189,12 @@ impl Display for OpCode {
             OpCode::MulILocal(v) => write!(f, "{:<15} {:>8}", "MUL_I_LOCAL", v),
             OpCode::DivILocal(v) => write!(f, "{:<15} {:>8}", "DIV_I_LOCAL", v),
             OpCode::ModILocal(v) => write!(f, "{:<15} {:>8}", "MOD_I_LOCAL", v),
+            OpCode::AddLocal(v) => write!(f, "{:<15} {:>8}", "ADD_LOCAL", v),
+            OpCode::SubLocal(v) => write!(f, "{:<15} {:>8}", "SUB_LOCAL", v),
+            OpCode::MulLocal(v) => write!(f, "{:<15} {:>8}", "MUL_LOCAL", v),
+            OpCode::DivLocal(v) => write!(f, "{:<15} {:>8}", "DIV_LOCAL", v),
+            OpCode::LessLocal(v) => write!(f, "{:<15} {:>8}", "LESS_LOCAL", v),
+            OpCode::GreaterLocal(v) => write!(f, "{:<15} {:>8}", "GREATER_LOCAL", v),
         }
     }
 }
@@ -1,26 +1,201 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::{hash_map::RandomState, BTreeMap, HashMap},
+    hash::{BuildHasher, Hasher},
+    io::{self, BufWriter, Write},
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
 use crate::rlox::{
     error::LoxError,
-    types::{FuncType, Function, Literal},
+    types::{BoundMethod, Class, FuncType, Function, Instance, Literal, MapKey, NativeFunction},
 };
 
-use super::opcode::OpCode;
+use super::{native::natives, opcode::OpCode};
 
 pub struct VirtualMachine {
     stack: Vec<Literal>,
     globals: HashMap<Rc<String>, Literal>,
+    /// What each global written by the running REPL line held before the
+    /// line first wrote it, `None` for one it defined. Outside the REPL
+    /// nothing is rolled back and this stays `None`.
+    journal: Option<HashMap<Rc<String>, Option<Literal>>>,
     is_repl: bool,
     frames: Vec<CallFrame>,
+    /// Where `print` writes to. The output is buffered rather than written
+    /// line by line, and flushed whenever control leaves the VM.
+    out: BufWriter<Box<dyn Write>>,
+    is_profile: bool,
+    profile: HashMap<&'static str, usize>,
+    history_size: usize,
+    budget: Option<usize>,
+    /// How long a single `interpret` call may run, set from the REPL.
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    steps: usize,
+    is_len_truthy: bool,
+    /// Whether the file natives may touch the file system.
+    allow_io: bool,
+    /// For each source line with code on it, whether it has been executed.
+    coverage: Option<BTreeMap<usize, bool>>,
+    /// State of the xorshift generator behind `random()`.
+    rng: u64,
+    /// When the VM was created, which `clock()` counts from.
+    started: Instant,
 }
 
 impl VirtualMachine {
     pub fn new() -> Self {
+        let mut globals = HashMap::with_capacity(1024);
+        for native in natives() {
+            globals.insert(Rc::new(native.name.to_string()), Rc::new(native).into());
+        }
+
         Self {
             frames: Default::default(),
             is_repl: std::env::var("RLOX_RUN_MODE").unwrap() == "R",
             stack: Vec::with_capacity(1024),
-            globals: HashMap::with_capacity(1024),
+            globals,
+            journal: None,
+            out: BufWriter::new(Box::new(io::stdout())),
+            is_profile: std::env::var("RLOX_PROFILE").is_ok(),
+            profile: Default::default(),
+            history_size: std::env::var("RLOX_REPL_HISTORY")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(3),
+            budget: std::env::var("RLOX_BUDGET")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            timeout: None,
+            deadline: None,
+            steps: 0,
+            is_len_truthy: std::env::var("RLOX_LEN_TRUTHY").is_ok(),
+            allow_io: std::env::var("RLOX_ALLOW_IO").is_ok(),
+            coverage: std::env::var("RLOX_COVERAGE")
+                .is_ok()
+                .then(Default::default),
+            rng: seed_rng(),
+            started: Instant::now(),
+        }
+    }
+
+    /// A VM whose `print` output goes to `out` instead of stdout.
+    #[cfg(test)]
+    pub fn with_output(out: impl Write + 'static) -> Self {
+        Self {
+            out: BufWriter::new(Box::new(out)),
+            ..Self::new()
+        }
+    }
+
+    /// Limits how many opcodes a single `interpret` call may execute, after
+    /// which it aborts with a runtime error. `None` means unlimited.
+    #[allow(unused)]
+    pub fn set_budget(&mut self, budget: Option<usize>) {
+        self.budget = budget;
+    }
+
+    /// Limits how long a single `interpret` call may run, after which it
+    /// aborts with a runtime error. `None` means unlimited.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    #[cfg(test)]
+    pub fn set_allow_io(&mut self, allow_io: bool) {
+        self.allow_io = allow_io;
+    }
+
+    pub fn allow_io(&self) -> bool {
+        self.allow_io
+    }
+
+    /// Keeps the results of the latest REPL lines in the globals `_`, `_1`,
+    /// `_2`, ..., with `_` the most recent. The number of results kept is
+    /// read from `RLOX_REPL_HISTORY` and defaults to 3.
+    pub fn record_result(&mut self, value: Literal) {
+        if self.history_size == 0 {
+            return;
+        }
+
+        let name = |i: usize| {
+            if i == 0 {
+                Rc::new(String::from("_"))
+            } else {
+                Rc::new(format!("_{}", i))
+            }
+        };
+
+        for i in (1..self.history_size).rev() {
+            if let Some(prev) = self.globals.get(&name(i - 1)).cloned() {
+                self.globals.insert(name(i), prev);
+            }
+        }
+        self.globals.insert(name(0), value);
+    }
+
+    /// The next number from the generator, in `[0, 1)`.
+    pub fn next_random(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Seconds since the VM was created.
+    pub fn elapsed(&self) -> f64 {
+        self.started.elapsed().as_secs_f64()
+    }
+
+    pub fn flush(&mut self) {
+        let _ = self.out.flush();
+    }
+
+    /// What has to happen once the program is done, however it ends: the
+    /// buffered output is written out before the profile and coverage
+    /// reports that follow it.
+    pub fn shutdown(&mut self) {
+        self.flush();
+        self.print_profile();
+        self.print_coverage();
+    }
+
+    fn print_coverage(&self) {
+        let Some(coverage) = &self.coverage else {
+            return;
+        };
+
+        let hit = coverage.values().filter(|hit| **hit).count();
+        println!(
+            "\x1b[1;90m[COVERAGE]: \x1b[0m{} of {} lines executed",
+            hit,
+            coverage.len()
+        );
+        for (line, _) in coverage.iter().filter(|(_, hit)| !**hit) {
+            println!("\x1b[1;90m[COVERAGE]: \x1b[0mline {} not executed", line);
+        }
+    }
+
+    fn print_profile(&self) {
+        if !self.is_profile {
+            return;
+        }
+
+        let mut counts = self.profile.iter().collect::<Vec<_>>();
+        counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+        let total: usize = counts.iter().map(|(_, c)| **c).sum();
+        println!("\x1b[1;90m[PROFILE]: \x1b[0m{} opcodes executed", total);
+        for (name, count) in counts {
+            println!(
+                "\x1b[1;90m[PROFILE]: \x1b[0m{:<15} {:>12} {:>6.2}%",
+                name,
+                count,
+                *count as f64 * 100.0 / total as f64
+            );
         }
     }
 
@@ -44,6 +219,16 @@ impl VirtualMachine {
         self.stack.last().unwrap()
     }
 
+    #[inline]
+    fn stack_top_is_true(&self) -> bool {
+        let value = self.stack_top_ref();
+        if self.is_len_truthy {
+            value.is_len_true()
+        } else {
+            value.is_true()
+        }
+    }
+
     #[inline]
     fn stack_top_mut(&mut self) -> &mut Literal {
         self.stack.last_mut().unwrap()
@@ -54,13 +239,14 @@ impl VirtualMachine {
         self.stack.get(self.stack.len() - i - 1).unwrap()
     }
 
-    fn binary_add(&mut self) -> Result<(), &'static str> {
-        let right = self.pop();
-        if self.stack_nth(1).is_num() && right.is_num() {
+    // The binary operators take the right operand already off the stack,
+    // so the fused opcodes can hand them a local instead.
+    fn binary_add(&mut self, right: &Literal) -> Result<(), &'static str> {
+        if self.stack_top_ref().is_num() && right.is_num() {
             let right = right.get_num().unwrap();
             let left = self.stack_top_ref().get_num().unwrap();
-            *self.stack_top_mut() = (left + right).into();
-        } else if self.stack_nth(1).is_string() {
+            *self.stack_top_mut() = num_add(left, right)?;
+        } else if self.stack_top_ref().is_string() {
             let right = right.to_string();
             let left = self.stack_top_ref().get_string().unwrap().to_string();
             *self.stack_top_mut() = Rc::new(left + &right).into();
@@ -70,89 +256,93 @@ impl VirtualMachine {
         Ok(())
     }
 
-    fn binary_sub(&mut self) -> Result<(), &'static str> {
-        if self.stack_nth(1).is_num() && self.stack_nth(0).is_num() {
-            let right = self.pop().get_num().unwrap();
-            let left = self.stack_top_ref().get_num().unwrap();
-            *self.stack_top_mut() = (left - right).into();
-            Ok(())
-        } else {
-            Err("Operands must be two numbers")
+    fn binary_arithmetic(
+        &mut self,
+        right: &Literal,
+        operate: Arithmetic,
+    ) -> Result<(), &'static str> {
+        match (self.stack_top_ref(), right) {
+            (Literal::Number(left), Literal::Number(right)) => {
+                *self.stack_top_mut() = operate(*left, *right)?;
+                Ok(())
+            }
+            _ => Err("Operands must be two numbers"),
         }
     }
 
-    fn binary_multi(&mut self) -> Result<(), &'static str> {
-        if self.stack_nth(1).is_num() && self.stack_nth(0).is_num() {
-            let right = self.pop().get_num().unwrap();
-            let left = self.stack_top_ref().get_num().unwrap();
-            *self.stack_top_mut() = (left * right).into();
-        } else {
-            return Err("Operands must be two numbers");
-        }
-        Ok(())
+    fn binary_sub(&mut self, right: &Literal) -> Result<(), &'static str> {
+        self.binary_arithmetic(right, num_sub)
     }
 
-    fn binary_div(&mut self) -> Result<(), &'static str> {
-        if self.stack_nth(1).is_num() && self.stack_nth(0).is_num() {
-            let right = self.pop().get_num().unwrap();
-            let left = self.stack_top_ref().get_num().unwrap();
-            if right == 0.0 {
-                return Err("divisor cannot be 0.");
-            }
-            *self.stack_top_mut() = (left / right).into();
-        } else {
-            return Err("Operands must be two numbers");
-        }
-        Ok(())
+    fn binary_multi(&mut self, right: &Literal) -> Result<(), &'static str> {
+        self.binary_arithmetic(right, num_mul)
     }
 
-    fn binary_mod(&mut self) -> Result<(), &'static str> {
-        if self.stack_nth(1).is_num() && self.stack_nth(0).is_num() {
-            let right = self.pop().get_num().unwrap() as i64;
-            let left = self.stack_top_ref().get_num().unwrap() as i64;
-            if right == 0 {
-                return Err("divisor cannot be 0.");
-            }
-            *self.stack_top_mut() = ((left % right) as f64).into();
-        } else {
-            return Err("Operands must be two numbers");
-        }
-        Ok(())
+    fn binary_div(&mut self, right: &Literal) -> Result<(), &'static str> {
+        self.binary_arithmetic(right, num_div)
     }
 
-    fn binary_eq(&mut self) {
-        let right = &self.pop();
+    fn binary_mod(&mut self, right: &Literal) -> Result<(), &'static str> {
+        self.binary_arithmetic(right, num_mod)
+    }
+
+    fn binary_eq(&mut self, right: &Literal) {
         let left = self.stack_top_ref();
-        *self.stack_top_mut() = (left == right).into();
+        *self.stack_top_mut() = left.is_equal(right).into();
     }
 
-    fn binary_less(&mut self) -> Result<(), &'static str> {
-        if self.stack_nth(1).is_num() && self.stack_nth(0).is_num() {
-            let right = self.pop().get_num().unwrap();
-            let left = self.stack_top_ref().get_num().unwrap();
-            *self.stack_top_mut() = (left < right).into();
-            Ok(())
-        } else {
-            Err("Operands must be two numbers")
-        }
+    fn binary_compare(&mut self, right: &Literal, expected: Ordering) -> Result<(), &'static str> {
+        let ordering = self.stack_top_ref().compare(right)?;
+        *self.stack_top_mut() = (ordering == expected).into();
+        Ok(())
     }
 
-    fn binary_greater(&mut self) -> Result<(), &'static str> {
-        if self.stack_nth(1).is_num() && self.stack_nth(0).is_num() {
-            let right = self.pop().get_num().unwrap();
-            let left = self.stack_top_ref().get_num().unwrap();
-            *self.stack_top_mut() = (left > right).into();
-            Ok(())
-        } else {
-            Err("Operands must be two numbers")
-        }
+    fn binary_less(&mut self, right: &Literal) -> Result<(), &'static str> {
+        self.binary_compare(right, Ordering::Less)
+    }
+
+    fn binary_greater(&mut self, right: &Literal) -> Result<(), &'static str> {
+        self.binary_compare(right, Ordering::Greater)
     }
 
-    pub fn run(&mut self) -> Result<(), LoxError> {
+    /// Runs the frame on top of `frames` until it returns to a caller at
+    /// `stop_depth`, so that natives can run a function to completion in the
+    /// middle of another one.
+    fn run(&mut self, stop_depth: usize) -> Result<Literal, LoxError> {
         let mut frame = self.frames.pop().unwrap();
         let mut base = frame.slot;
 
-        while let Some(opcode) = frame.read_opcode() {
+        loop {
+            if let Some(coverage) = &mut self.coverage {
+                if let Some((line, _)) = frame.function.chunk.get_position(frame.ip) {
+                    coverage.entry(line).and_modify(|hit| *hit = true);
+                }
+            }
+
+            let Some(opcode) = frame.read_opcode() else {
+                break;
+            };
+
+            if self.is_profile {
+                *self.profile.entry(opcode.name()).or_default() += 1;
+            }
+
+            self.steps += 1;
+            if self.budget.is_some_and(|budget| self.steps > budget) {
+                let name = opcode.name();
+                return Err(self.create_runtime_error(&frame, name, "Execution limit exceeded."));
+            }
+
+            // reading the clock is too slow to do on every opcode
+            if self.steps.is_multiple_of(1024)
+                && self
+                    .deadline
+                    .is_some_and(|deadline| Instant::now() > deadline)
+            {
+                let name = opcode.name();
+                return Err(self.create_runtime_error(&frame, name, "Execution timed out."));
+            }
+
             // sleep(Duration::from_millis(500));
             // println!(
             //     "[{}] --> [{}]",
@@ -164,6 +354,10 @@ impl VirtualMachine {
             //         .join(", ")
             // );
 
+            // Set by an operator whose left operand is an instance with a
+            // method for it, which is then called like any other method.
+            let mut overload = None;
+
             match opcode {
                 OpCode::Load(value) => {
                     let value = value.clone();
@@ -181,26 +375,51 @@ impl VirtualMachine {
                         ));
                     }
                 }
-                OpCode::Add => self
-                    .binary_add()
-                    .map_err(|e| self.create_runtime_error(&frame, "+", e))?,
-                OpCode::Sub => self
-                    .binary_sub()
-                    .map_err(|e| self.create_runtime_error(&frame, "-", e))?,
-                OpCode::Mul => self
-                    .binary_multi()
-                    .map_err(|e| self.create_runtime_error(&frame, "*", e))?,
-                OpCode::Div => self
-                    .binary_div()
-                    .map_err(|e| self.create_runtime_error(&frame, "/", e))?,
-                OpCode::Mod => self
-                    .binary_mod()
-                    .map_err(|e| self.create_runtime_error(&frame, "%", e))?,
+                OpCode::Add => {
+                    let right = self.pop();
+                    if let Err(e) = self.binary_add(&right) {
+                        let method = self.overload("__add__", &right);
+                        overload =
+                            Some(method.ok_or_else(|| self.create_runtime_error(&frame, "+", e))?);
+                    }
+                }
+                OpCode::Sub => {
+                    let right = self.pop();
+                    if let Err(e) = self.binary_sub(&right) {
+                        let method = self.overload("__sub__", &right);
+                        overload =
+                            Some(method.ok_or_else(|| self.create_runtime_error(&frame, "-", e))?);
+                    }
+                }
+                OpCode::Mul => {
+                    let right = self.pop();
+                    if let Err(e) = self.binary_multi(&right) {
+                        let method = self.overload("__mul__", &right);
+                        overload =
+                            Some(method.ok_or_else(|| self.create_runtime_error(&frame, "*", e))?);
+                    }
+                }
+                OpCode::Div => {
+                    let right = self.pop();
+                    if let Err(e) = self.binary_div(&right) {
+                        let method = self.overload("__div__", &right);
+                        overload =
+                            Some(method.ok_or_else(|| self.create_runtime_error(&frame, "/", e))?);
+                    }
+                }
+                OpCode::Mod => {
+                    let right = self.pop();
+                    if let Err(e) = self.binary_mod(&right) {
+                        let method = self.overload("__mod__", &right);
+                        overload =
+                            Some(method.ok_or_else(|| self.create_runtime_error(&frame, "%", e))?);
+                    }
+                }
                 OpCode::Return => {
                     let value = self.pop();
-                    if self.frames.is_empty() {
+                    if self.frames.len() == stop_depth {
                         self.pop();
-                        break;
+                        return Ok(value);
                     }
                     unsafe {
                         self.stack.set_len(frame.slot);
@@ -210,58 +429,95 @@ impl VirtualMachine {
                     base = frame.slot;
                 }
                 OpCode::Not => {
-                    let value = !self.stack_top_ref().is_true();
+                    let value = !self.stack_top_is_true();
                     *self.stack_top_mut() = value.into();
                 }
-                OpCode::Eq => self.binary_eq(),
-                OpCode::Less => self
-                    .binary_less()
-                    .map_err(|e| self.create_runtime_error(&frame, "<", e))?,
-                OpCode::Greater => self
-                    .binary_greater()
-                    .map_err(|e| self.create_runtime_error(&frame, ">", e))?,
-                OpCode::Print => {
-                    let value = self.pop();
+                OpCode::Eq => {
+                    let right = self.pop();
+                    overload = self.overload("__eq__", &right);
+                    if overload.is_none() {
+                        self.binary_eq(&right);
+                    }
+                }
+                OpCode::Less => {
+                    let right = self.pop();
+                    if let Err(e) = self.binary_less(&right) {
+                        let method = self.overload("__lt__", &right);
+                        overload =
+                            Some(method.ok_or_else(|| self.create_runtime_error(&frame, "<", e))?);
+                    }
+                }
+                OpCode::Greater => {
+                    let right = self.pop();
+                    if let Err(e) = self.binary_greater(&right) {
+                        let method = self.overload("__gt__", &right);
+                        overload =
+                            Some(method.ok_or_else(|| self.create_runtime_error(&frame, ">", e))?);
+                    }
+                }
+                OpCode::Print(count) => {
+                    // the values are written from the stack and then dropped,
+                    // so printing them doesn't allocate
+                    let start = self.stack.len() - *count;
                     if self.is_repl {
-                        println!("\x1b[1;34m[REPL]: \x1b[0m{}", value);
-                    } else {
-                        println!("{}", value);
+                        write!(self.out, "\x1b[1;34m[REPL]: \x1b[0m")?;
+                    }
+                    for (i, value) in self.stack[start..].iter().enumerate() {
+                        if i > 0 {
+                            write!(self.out, " ")?;
+                        }
+                        write!(self.out, "{}", value)?;
                     }
+                    writeln!(self.out)?;
+                    self.stack.truncate(start);
                 }
-                OpCode::Pop => {
+                OpCode::Assert(source) => {
+                    if !self.stack_top_is_true() {
+                        let source = source.clone();
+                        return Err(self.create_runtime_error(
+                            &frame,
+                            "assert",
+                            format!("Assertion failed: {}", source).as_str(),
+                        ));
+                    }
+                    self.pop();
+                }
+                OpCode::Pop | OpCode::Discard => {
                     self.stack.pop();
                 }
                 OpCode::DefineGlobal(name) => {
                     let name = name.clone();
                     let value = self.pop();
+                    self.journal_global(&name);
                     self.globals.insert(name, value);
                 }
-                OpCode::GetGlobal(name) => {
-                    let name = name.clone();
-                    if self.globals.contains_key(&name) {
-                        let value = self.globals.get(&name).unwrap().clone();
+                OpCode::GetGlobal(name) => match self.globals.get(name) {
+                    Some(value) => {
+                        let value = value.clone();
                         self.push(value);
-                    } else {
+                    }
+                    None => {
+                        let name = name.clone();
                         return Err(self.create_runtime_error(
                             &frame,
                             &name,
                             format!("Undefined variable `{}`.", &name).as_str(),
                         ));
                     }
-                }
+                },
                 OpCode::SetGlobal(name) => {
-                    let name = name.clone();
-                    if self.globals.contains_key(&name) {
-                        let value = self.stack_top_clone();
-                        self.globals.entry(name).and_modify(|v| {
-                            *v = value;
-                        });
-                    } else {
-                        return Err(self.create_runtime_error(
-                            &frame,
-                            &name,
-                            format!("Undefined variable `{}`.", &name).as_str(),
-                        ));
+                    let value = self.stack_top_clone();
+                    self.journal_global(name);
+                    match self.globals.get_mut(name) {
+                        Some(v) => *v = value,
+                        None => {
+                            let name = name.clone();
+                            return Err(self.create_runtime_error(
+                                &frame,
+                                &name,
+                                format!("Undefined variable `{}`.", &name).as_str(),
+                            ));
+                        }
                     }
                 }
                 OpCode::GetLocal(slot) => {
@@ -275,15 +531,13 @@ impl VirtualMachine {
                     self.stack[slot] = value;
                 }
                 OpCode::JumpIfFalse(offset) => {
-                    let offset = offset;
-                    if !self.stack_top_ref().is_true() {
+                    if !self.stack_top_is_true() {
                         let offset = *offset;
                         frame.ip += offset;
                     }
                 }
                 OpCode::JumpIfTrue(offset) => {
-                    let offset = offset;
-                    if self.stack_top_ref().is_true() {
+                    if self.stack_top_is_true() {
                         let offset = *offset;
                         frame.ip += offset;
                     }
@@ -298,253 +552,431 @@ impl VirtualMachine {
                 }
                 OpCode::Call(arity) => {
                     let arity = *arity;
-                    let callee = self.stack_nth(arity).get_function()?;
-                    if callee.arity != arity {
-                        return Err(self.create_runtime_error(
-                            &frame,
-                            &callee.name,
-                            format!("Expect {} arguments but got {}.", callee.arity, arity)
-                                .as_str(),
-                        ));
+                    let callee = self.stack_nth(arity);
+                    if let Err(msg) = callee.check_arity(arity) {
+                        let name = match callee {
+                            Literal::Native(native) => native.name.to_string(),
+                            Literal::Function(function)
+                                if function.func_type == FuncType::Lambda =>
+                            {
+                                "lambda".into()
+                            }
+                            Literal::Class(class) => class.name.to_string(),
+                            Literal::BoundMethod(bound) => bound.method.name.to_string(),
+                            callee => callee.get_function()?.name.to_string(),
+                        };
+                        return Err(self.create_runtime_error(&frame, &name, &msg));
+                    }
+                    if let Literal::Native(native) = callee {
+                        let native = native.clone();
+                        self.call_native(&frame, native, arity)?;
+                        continue;
                     }
+
+                    let Some(callee) = self.prepare_call(self.stack.len() - arity - 1)? else {
+                        continue;
+                    };
                     self.frames.push(frame);
                     frame = CallFrame::new(callee, 0, self.stack.len() - arity - 1);
                     base = frame.slot;
                 }
-                OpCode::AddIGlobal(name) => {
-                    let name = name.clone();
-                    if self.globals.contains_key(&name) {
-                        let target = self.globals.get(&name).unwrap().clone();
-                        if target.is_num() && self.stack_top_ref().is_num() {
-                            let v =
-                                target.get_num().unwrap() + self.stack_top_ref().get_num().unwrap();
-                            self.globals.insert(name, v.into());
-                        } else {
-                            return Err(self.create_runtime_error(
-                                &frame,
-                                "+=",
-                                "Operator '+=' can only be used on number",
-                            ));
-                        }
-                    } else {
+                OpCode::Class(name) => {
+                    let class = Rc::new(Class::new(name.clone(), None));
+                    self.push(class.into());
+                }
+                OpCode::Inherit(name) => {
+                    let Literal::Class(superclass) = self.pop() else {
+                        let name = name.clone();
                         return Err(self.create_runtime_error(
                             &frame,
                             &name,
-                            format!("Undefined variable `{}`.", &name).as_str(),
+                            format!("Superclass of `{}` must be a class.", &name).as_str(),
                         ));
+                    };
+                    let class = Rc::new(Class::new(name.clone(), Some(superclass)));
+                    self.push(class.into());
+                }
+                OpCode::Method(name) | OpCode::StaticMethod(name) => {
+                    let method = self.pop().get_function()?;
+                    if let Literal::Class(class) = self.stack_top_ref() {
+                        let methods = match opcode {
+                            OpCode::Method(_) => &class.methods,
+                            _ => &class.static_methods,
+                        };
+                        methods.borrow_mut().insert(name.clone(), method);
                     }
                 }
-                OpCode::SubIGlobal(name) => {
-                    let name = name.clone();
-                    if self.globals.contains_key(&name) {
-                        let target = self.globals.get(&name).unwrap().clone();
-                        if target.is_num() && self.stack_top_ref().is_num() {
-                            let v = target.get_num()? - self.stack_top_ref().get_num()?;
-                            self.globals.insert(name, v.into());
-                        } else {
+                OpCode::StaticField(name) => {
+                    let value = self.pop();
+                    if let Literal::Class(class) = self.stack_top_ref() {
+                        class.static_fields.borrow_mut().insert(name.clone(), value);
+                    }
+                }
+                OpCode::GetProperty(name) => {
+                    let value = match self.stack_top_ref() {
+                        Literal::Instance(instance) => instance.get(name),
+                        Literal::Class(class) => class
+                            .find_static_field(name)
+                            .or_else(|| class.find_static_method(name).map(Literal::from)),
+                        _ => {
+                            let name = name.clone();
                             return Err(self.create_runtime_error(
                                 &frame,
-                                "-=",
-                                "Operator '-=' can only be used on number",
+                                &name,
+                                "Only instances and classes have properties.",
                             ));
                         }
-                    } else {
-                        return Err(self.create_runtime_error(
-                            &frame,
-                            &name,
-                            format!("Undefined variable `{}`.", &name).as_str(),
-                        ));
-                    }
-                }
-                OpCode::MulIGlobal(name) => {
-                    let name = name.clone();
-                    if self.globals.contains_key(&name) {
-                        let target = self.globals.get(&name).unwrap().clone();
-                        if target.is_num() && self.stack_top_ref().is_num() {
-                            let v = target.get_num()? * self.stack_top_ref().get_num()?;
-                            self.globals.insert(name, v.into());
-                        } else {
+                    };
+                    match value {
+                        Some(value) => *self.stack_top_mut() = value,
+                        None => {
+                            let name = name.clone();
                             return Err(self.create_runtime_error(
                                 &frame,
-                                "*=",
-                                "Operator '*=' can only be used on number",
+                                &name,
+                                format!("Undefined property `{}`.", &name).as_str(),
                             ));
                         }
-                    } else {
-                        return Err(self.create_runtime_error(
-                            &frame,
-                            &name,
-                            format!("Undefined variable `{}`.", &name).as_str(),
-                        ));
                     }
                 }
-                OpCode::DivIGlobal(name) => {
+                OpCode::GetSuper(name) => {
                     let name = name.clone();
-                    if self.globals.contains_key(&name) {
-                        let target = self.globals.get(&name).unwrap().clone();
-                        if target.is_num() && self.stack_top_ref().is_num() {
-                            let divisor = self.stack_top_ref().get_num()?;
-
-                            if divisor == 0.0 {
-                                return Err(self.create_runtime_error(
-                                    &frame,
-                                    "%=",
-                                    "divisor cannot be 0.",
-                                ));
+                    let value = match self.stack_top_ref() {
+                        Literal::Instance(instance) => {
+                            let method = instance
+                                .class
+                                .owner_of(&frame.function)
+                                .and_then(|class| class.superclass.as_ref()?.find_method(&name));
+                            match method {
+                                Some(method) => Some(
+                                    Rc::new(BoundMethod::new(self.stack_top_clone(), method))
+                                        .into(),
+                                ),
+                                // Fields belong to the instance rather than
+                                // to one of its classes, so `super.x` is the
+                                // same field as `self.x`.
+                                None => instance.fields.borrow().get(&name).cloned(),
                             }
-
-                            let v = target.get_num()? / divisor;
-                            self.globals.insert(name, v.into());
-                        } else {
-                            return Err(self.create_runtime_error(
-                                &frame,
-                                "/=",
-                                "Operator '/=' can only be used on number",
-                            ));
                         }
-                    } else {
+                        _ => None,
+                    };
+                    let Some(value) = value else {
                         return Err(self.create_runtime_error(
                             &frame,
                             &name,
-                            format!("Undefined variable `{}`.", &name).as_str(),
+                            format!("Undefined superclass property `{}`.", &name).as_str(),
                         ));
+                    };
+                    *self.stack_top_mut() = value;
+                }
+                OpCode::BuildArray(count) => {
+                    let elements = self.stack.split_off(self.stack.len() - *count);
+                    self.push(Literal::Array(Rc::new(RefCell::new(elements))));
+                }
+                OpCode::BuildMap(count) => {
+                    let entries = self.stack.split_off(self.stack.len() - 2 * *count);
+                    let mut map = HashMap::with_capacity(*count);
+                    for entry in entries.chunks(2) {
+                        let key = MapKey::new(&entry[0])
+                            .map_err(|e| self.create_runtime_error(&frame, "{", &e))?;
+                        map.insert(key, entry[1].clone());
                     }
+                    self.push(Literal::Map(Rc::new(RefCell::new(map))));
                 }
-                OpCode::ModIGlobal(name) => {
-                    let name = name.clone();
-                    if self.globals.contains_key(&name) {
-                        let target = self.globals.get(&name).unwrap().clone();
-                        if target.is_num() && self.stack_top_ref().is_num() {
-                            let divisor = self.stack_top_ref().get_num()? as i64;
-
-                            if divisor == 0 {
-                                return Err(self.create_runtime_error(
-                                    &frame,
-                                    "%=",
-                                    "divisor cannot be 0.",
-                                ));
-                            }
-
-                            let v = (target.get_num()? as i64 % divisor) as f64;
-                            self.globals.insert(name, v.into());
-                        } else {
-                            return Err(self.create_runtime_error(
-                                &frame,
-                                "%=",
-                                "Operator '%=' can only be used on number",
-                            ));
-                        }
-                    } else {
+                OpCode::Index => {
+                    let index = self.pop();
+                    let value = self
+                        .stack_top_ref()
+                        .get_index(&index)
+                        .map_err(|e| self.create_runtime_error(&frame, "[", &e))?;
+                    *self.stack_top_mut() = value;
+                }
+                OpCode::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    self.stack_top_ref()
+                        .set_index(&index, value.clone())
+                        .map_err(|e| self.create_runtime_error(&frame, "[", &e))?;
+                    *self.stack_top_mut() = value;
+                }
+                OpCode::SetProperty(name) => {
+                    let value = self.pop();
+                    let Literal::Instance(instance) = self.stack_top_ref() else {
+                        let name = name.clone();
                         return Err(self.create_runtime_error(
                             &frame,
                             &name,
-                            format!("Undefined variable `{}`.", &name).as_str(),
+                            "Only instances have fields.",
                         ));
-                    }
-                }
-                OpCode::AddILocal(slot) => {
-                    let slot = slot + base;
-                    let target = &self.stack[slot];
-                    if target.is_num() && self.stack_top_ref().is_num() {
-                        let v = target.get_num()? + self.stack_top_ref().get_num()?;
-                        self.stack[slot] = v.into();
-                    } else {
+                    };
+                    if instance.frozen.get() {
+                        let name = name.clone();
                         return Err(self.create_runtime_error(
                             &frame,
-                            "+=",
-                            "Operator '+=' can only be used on number",
+                            &name,
+                            "Cannot modify frozen instance.",
                         ));
                     }
+                    instance
+                        .fields
+                        .borrow_mut()
+                        .insert(name.clone(), value.clone());
+                    *self.stack_top_mut() = value;
+                }
+                OpCode::AddIGlobal(name) => {
+                    let name = name.clone();
+                    self.operate_and_assign_global(&frame, name, "+=", num_add)?;
+                }
+                OpCode::SubIGlobal(name) => {
+                    let name = name.clone();
+                    self.operate_and_assign_global(&frame, name, "-=", num_sub)?;
+                }
+                OpCode::MulIGlobal(name) => {
+                    let name = name.clone();
+                    self.operate_and_assign_global(&frame, name, "*=", num_mul)?;
+                }
+                OpCode::DivIGlobal(name) => {
+                    let name = name.clone();
+                    self.operate_and_assign_global(&frame, name, "/=", num_div)?;
+                }
+                OpCode::ModIGlobal(name) => {
+                    let name = name.clone();
+                    self.operate_and_assign_global(&frame, name, "%=", num_mod)?;
+                }
+                OpCode::AddILocal(slot) => {
+                    let slot = slot + base;
+                    self.operate_and_assign_local(&frame, slot, "+=", num_add)?;
                 }
                 OpCode::SubILocal(slot) => {
                     let slot = slot + base;
-                    let target = &self.stack[slot];
-                    if target.is_num() && self.stack_top_ref().is_num() {
-                        let v = target.get_num()? - self.stack_top_ref().get_num()?;
-                        self.stack[slot] = v.into();
-                    } else {
-                        return Err(self.create_runtime_error(
-                            &frame,
-                            "-=",
-                            "Operator '-=' can only be used on number",
-                        ));
-                    }
+                    self.operate_and_assign_local(&frame, slot, "-=", num_sub)?;
                 }
                 OpCode::MulILocal(slot) => {
                     let slot = slot + base;
-                    let target = &self.stack[slot];
-                    if target.is_num() && self.stack_top_ref().is_num() {
-                        let v = target.get_num()? * self.stack_top_ref().get_num()?;
-                        self.stack[slot] = v.into();
-                    } else {
-                        return Err(self.create_runtime_error(
-                            &frame,
-                            "*=",
-                            "Operator '*=' can only be used on number",
-                        ));
-                    }
+                    self.operate_and_assign_local(&frame, slot, "*=", num_mul)?;
                 }
                 OpCode::DivILocal(slot) => {
                     let slot = slot + base;
-                    let target = &self.stack[slot];
-                    if target.is_num() && self.stack_top_ref().is_num() {
-                        let divisor = self.stack_top_ref().get_num()?;
-
-                        if divisor == 0.0 {
-                            return Err(self.create_runtime_error(
-                                &frame,
-                                "/=",
-                                "divisor cannot be 0.",
-                            ));
-                        }
-
-                        let v = target.get_num()? / divisor;
-                        self.stack[slot] = v.into();
-                    } else {
-                        return Err(self.create_runtime_error(
-                            &frame,
-                            "/=",
-                            "Operator '/=' can only be used on number",
-                        ));
-                    }
+                    self.operate_and_assign_local(&frame, slot, "/=", num_div)?;
                 }
                 OpCode::ModILocal(slot) => {
                     let slot = slot + base;
-                    let target = &self.stack[slot];
-                    if target.is_num() && self.stack_top_ref().is_num() {
-                        let divisor = self.stack_top_ref().get_num()?;
-
-                        if divisor == 0.0 {
-                            return Err(self.create_runtime_error(
-                                &frame,
-                                "%=",
-                                "divisor cannot be 0.",
-                            ));
-                        }
-
-                        let v = target.get_num()? / divisor;
-                        self.stack[slot] = v.into();
-                    } else {
-                        return Err(self.create_runtime_error(
-                            &frame,
-                            "%=",
-                            "Operator '%=' can only be used on number",
-                        ));
+                    self.operate_and_assign_local(&frame, slot, "%=", num_mod)?;
+                }
+                OpCode::AddLocal(slot) => {
+                    let right = self.stack[slot + base].clone();
+                    if let Err(e) = self.binary_add(&right) {
+                        let method = self.overload("__add__", &right);
+                        overload =
+                            Some(method.ok_or_else(|| self.create_runtime_error(&frame, "+", e))?);
+                    }
+                }
+                OpCode::SubLocal(slot) => {
+                    let right = self.stack[slot + base].clone();
+                    if let Err(e) = self.binary_sub(&right) {
+                        let method = self.overload("__sub__", &right);
+                        overload =
+                            Some(method.ok_or_else(|| self.create_runtime_error(&frame, "-", e))?);
+                    }
+                }
+                OpCode::MulLocal(slot) => {
+                    let right = self.stack[slot + base].clone();
+                    if let Err(e) = self.binary_multi(&right) {
+                        let method = self.overload("__mul__", &right);
+                        overload =
+                            Some(method.ok_or_else(|| self.create_runtime_error(&frame, "*", e))?);
+                    }
+                }
+                OpCode::DivLocal(slot) => {
+                    let right = self.stack[slot + base].clone();
+                    if let Err(e) = self.binary_div(&right) {
+                        let method = self.overload("__div__", &right);
+                        overload =
+                            Some(method.ok_or_else(|| self.create_runtime_error(&frame, "/", e))?);
+                    }
+                }
+                OpCode::LessLocal(slot) => {
+                    let right = self.stack[slot + base].clone();
+                    if let Err(e) = self.binary_less(&right) {
+                        let method = self.overload("__lt__", &right);
+                        overload =
+                            Some(method.ok_or_else(|| self.create_runtime_error(&frame, "<", e))?);
+                    }
+                }
+                OpCode::GreaterLocal(slot) => {
+                    let right = self.stack[slot + base].clone();
+                    if let Err(e) = self.binary_greater(&right) {
+                        let method = self.overload("__gt__", &right);
+                        overload =
+                            Some(method.ok_or_else(|| self.create_runtime_error(&frame, ">", e))?);
                     }
                 }
             }
+
+            if let Some(method) = overload {
+                if let Err(msg) = Literal::from(method.clone()).check_arity(1) {
+                    return Err(self.create_runtime_error(&frame, &method.name, &msg));
+                }
+                self.frames.push(frame);
+                frame = CallFrame::new(method, 0, self.stack.len() - 2);
+                base = frame.slot;
+            }
+        }
+        Ok(Literal::Nil)
+    }
+
+    /// The method overloading an operator, when its left operand, on top of
+    /// the stack, is an instance whose class has one: `a + b` calls
+    /// `a.__add__(b)`, and likewise `__sub__`, `__mul__`, `__div__`,
+    /// `__mod__`, `__eq__`, `__lt__` and `__gt__`. Operators compiled to a
+    /// negated one use it too, so `a <= b` is `!a.__gt__(b)`. The right
+    /// operand is pushed back as the argument. Only `==` asks on every use,
+    /// the others only once they have failed on their operands, which keeps
+    /// arithmetic on numbers as fast as it was.
+    #[inline]
+    fn overload(&mut self, name: &str, right: &Literal) -> Option<Rc<Function>> {
+        let Literal::Instance(instance) = self.stack_top_ref() else {
+            return None;
+        };
+        let method = instance.class.find_method(&Rc::new(name.to_string()))?;
+        self.push(right.clone());
+        Some(method)
+    }
+
+    fn call_native(
+        &mut self,
+        frame: &CallFrame,
+        native: Rc<NativeFunction>,
+        arity: usize,
+    ) -> Result<(), LoxError> {
+        let args = self.stack.split_off(self.stack.len() - arity);
+        let value = (native.function)(self, &args)
+            .map_err(|e| self.create_runtime_error(frame, native.name, &e))?;
+        self.pop();
+        self.push(value);
+        Ok(())
+    }
+
+    /// Gets the function to run for the callee in `slot`, and puts what the
+    /// function sees as slot 0 there. Calling a class leaves a new instance
+    /// in the slot, and there is nothing to run when it has no `__init__`.
+    fn prepare_call(&mut self, slot: usize) -> Result<Option<Rc<Function>>, LoxError> {
+        match self.stack[slot].clone() {
+            Literal::Class(class) => {
+                let initializer = class.initializer();
+                self.stack[slot] = Rc::new(Instance::new(class)).into();
+                Ok(initializer)
+            }
+            Literal::BoundMethod(bound) => {
+                self.stack[slot] = bound.receiver.clone();
+                Ok(Some(bound.method.clone()))
+            }
+            callee => callee.get_function().map(Some),
+        }
+    }
+
+    /// Calls `callee` with `args` on behalf of a native and returns its
+    /// result. Whatever the call leaves behind on failure is discarded, so
+    /// the native may carry on after an error.
+    pub fn call(&mut self, callee: &Literal, args: &[Literal]) -> Result<Literal, LoxError> {
+        callee
+            .check_arity(args.len())
+            .map_err(|message| LoxError::UnexpectedError { message })?;
+
+        if let Literal::Native(native) = callee {
+            return (native.function)(self, args)
+                .map_err(|message| LoxError::UnexpectedError { message });
+        }
+
+        let stack_len = self.stack.len();
+        let depth = self.frames.len();
+        self.push(callee.clone());
+        self.stack.extend_from_slice(args);
+        let Some(function) = self.prepare_call(stack_len)? else {
+            let instance = self.pop();
+            self.stack.truncate(stack_len);
+            return Ok(instance);
+        };
+        self.frames.push(CallFrame::new(function, 0, stack_len));
+
+        let result = self.run(depth);
+        self.frames.truncate(depth);
+        self.stack.truncate(stack_len);
+        result
+    }
+
+    /// Records what `name` holds before the running REPL line first writes
+    /// it, so that the write can be undone if the line fails.
+    fn journal_global(&mut self, name: &Rc<String>) {
+        if let Some(journal) = &mut self.journal {
+            if !journal.contains_key(name) {
+                journal.insert(name.clone(), self.globals.get(name).cloned());
+            }
         }
+    }
+
+    fn operate_and_assign_global(
+        &mut self,
+        frame: &CallFrame,
+        name: Rc<String>,
+        op: &str,
+        operate: Arithmetic,
+    ) -> Result<(), LoxError> {
+        let target = match self.globals.get(&name) {
+            Some(target) => target.clone(),
+            None => {
+                return Err(self.create_runtime_error(
+                    frame,
+                    &name,
+                    format!("Undefined variable `{}`.", &name).as_str(),
+                ))
+            }
+        };
+
+        let value = operate_number(&target, self.stack_top_ref(), op, operate)
+            .map_err(|e| self.create_runtime_error(frame, op, &e))?;
+        self.journal_global(&name);
+        self.globals.insert(name, value);
         Ok(())
     }
 
-    pub fn interpret(&mut self, function: Function) -> Result<(), LoxError> {
+    fn operate_and_assign_local(
+        &mut self,
+        frame: &CallFrame,
+        slot: usize,
+        op: &str,
+        operate: Arithmetic,
+    ) -> Result<(), LoxError> {
+        let value = operate_number(&self.stack[slot], self.stack_top_ref(), op, operate)
+            .map_err(|e| self.create_runtime_error(frame, op, &e))?;
+        self.stack[slot] = value;
+        Ok(())
+    }
+
+    /// In REPL mode a line is executed as a transaction: if it fails with a
+    /// runtime error, every global defined or assigned by that line is rolled
+    /// back, so the next line sees the globals exactly as they were before.
+    /// Only the bindings are restored. Changes made through a global to an
+    /// array, map, instance or string builder stay, as the value is shared.
+    pub fn interpret(&mut self, function: Function) -> Result<Literal, LoxError> {
+        self.journal = self.is_repl.then(HashMap::new);
+        self.steps = 0;
+        self.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+
+        if let Some(coverage) = &mut self.coverage {
+            code_lines(&function, coverage);
+        }
+
         let func = Rc::new(function);
         self.push(func.clone().into());
         let frame = CallFrame::new(func, 0, self.stack.len());
         self.frames.push(frame);
 
-        match self.run() {
-            Ok(_) => {
+        let result = self.run(0);
+        self.flush();
+
+        match result {
+            Ok(value) => {
                 // println!(
                 //     "[{}]",
                 //     self.stack
@@ -553,10 +985,19 @@ impl VirtualMachine {
                 //         .collect::<Vec<String>>()
                 //         .join(", ")
                 // );
-                Ok(())
+                Ok(value)
             }
             Err(e) => {
+                // The error may surface several calls deep, so the frames of
+                // the callers are dropped along with their stack slots.
+                self.frames.clear();
                 self.stack.clear();
+                for (name, value) in self.journal.take().into_iter().flatten() {
+                    match value {
+                        Some(value) => self.globals.insert(name, value),
+                        None => self.globals.remove(&name),
+                    };
+                }
                 Err(e)
             }
         }
@@ -569,7 +1010,7 @@ impl VirtualMachine {
         for fm in self.frames.iter().rev() {
             let ip = fm.ip;
             let pos = fm.function.chunk.get_position(ip).unwrap();
-            if FuncType::Lambda == frame.function.func_type {
+            if FuncType::Lambda == fm.function.func_type {
                 msgs.push(format!("[{:2},{:2}] Error in Lambda", pos.0, pos.1))
             } else {
                 msgs.push(format!(
@@ -580,12 +1021,115 @@ impl VirtualMachine {
         }
         LoxError::RuntimeError {
             position: pos,
+            end: (pos.0, pos.1 + op.chars().count()),
             lexeme: Rc::new(op.into()),
             msg: msgs.join("\n"),
         }
     }
 }
 
+/// Seeds the generator from `RLOX_SEED` so that runs can be repeated, or
+/// from the entropy std keeps for hash maps otherwise.
+fn seed_rng() -> u64 {
+    let seed = std::env::var("RLOX_SEED")
+        .ok()
+        .and_then(|seed| seed.parse().ok())
+        .unwrap_or_else(|| RandomState::new().build_hasher().finish());
+    mix_seed(seed)
+}
+
+/// Mixes a seed with splitmix64, as xorshift must not start from 0 and
+/// starts poorly from small numbers.
+fn mix_seed(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (z ^ (z >> 31)).max(1)
+}
+
+/// Registers the lines `function` and the functions defined in it have code
+/// on. Instructions the convertor synthesizes sit on line 0 and are skipped.
+fn code_lines(function: &Function, coverage: &mut BTreeMap<usize, bool>) {
+    let chunk = &function.chunk;
+    for i in 0..chunk.len() {
+        if let Some((line, _)) = chunk.get_position(i).filter(|(line, _)| *line > 0) {
+            coverage.entry(line).or_default();
+        }
+        if let Some(OpCode::Load(Literal::Function(inner))) = chunk.get(i) {
+            code_lines(inner, coverage);
+        }
+    }
+}
+
+/// Numbers in rlox are always finite: an arithmetic operation whose result
+/// would be `NaN` or an infinity raises a runtime error at that operation
+/// instead of letting the value leak into the program.
+#[inline]
+fn check_finite(value: f64) -> Result<Literal, &'static str> {
+    if value.is_finite() {
+        Ok(value.into())
+    } else {
+        Err("Numeric result out of range.")
+    }
+}
+
+/// Integer-only operations take their operands as `i64`, which would
+/// silently drop the fraction of `2.5` or saturate a huge number, so such
+/// operands are rejected instead.
+#[inline]
+fn as_integer(value: f64) -> Result<i64, &'static str> {
+    if value.fract() == 0.0 && (-(2f64.powi(63))..2f64.powi(63)).contains(&value) {
+        Ok(value as i64)
+    } else {
+        Err("Operand must be an integer.")
+    }
+}
+
+type Arithmetic = fn(f64, f64) -> Result<Literal, &'static str>;
+
+// The arithmetic shared by the binary operators and the compound assignment
+// opcodes, so that `x %= y` always agrees with `x = x % y`.
+fn num_add(left: f64, right: f64) -> Result<Literal, &'static str> {
+    check_finite(left + right)
+}
+
+fn num_sub(left: f64, right: f64) -> Result<Literal, &'static str> {
+    check_finite(left - right)
+}
+
+fn num_mul(left: f64, right: f64) -> Result<Literal, &'static str> {
+    check_finite(left * right)
+}
+
+fn num_div(left: f64, right: f64) -> Result<Literal, &'static str> {
+    if right == 0.0 {
+        return Err("divisor cannot be 0.");
+    }
+    check_finite(left / right)
+}
+
+fn num_mod(left: f64, right: f64) -> Result<Literal, &'static str> {
+    let (left, right) = (as_integer(left)?, as_integer(right)?);
+    if right == 0 {
+        return Err("divisor cannot be 0.");
+    }
+    Ok(((left % right) as f64).into())
+}
+
+fn operate_number(
+    target: &Literal,
+    value: &Literal,
+    op: &str,
+    operate: Arithmetic,
+) -> Result<Literal, String> {
+    match (target, value) {
+        (Literal::Number(left), Literal::Number(right)) => {
+            operate(*left, *right).map_err(|e| e.to_string())
+        }
+        _ => Err(format!("Operator '{}' can only be used on number", op)),
+    }
+}
+
 #[derive(Debug)]
 struct CallFrame {
     pub function: Rc<Function>,
@@ -607,3 +1151,572 @@ impl CallFrame {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+        io::{self, BufWriter, Write},
+        rc::Rc,
+    };
+
+    use super::mix_seed;
+    use crate::rlox::error::LoxError;
+    use crate::rlox::lox::testing::{compile, compile_line, message, run, vm};
+
+    /// Counts the allocations made on each thread, so that a test can tell
+    /// how many its own code makes while other tests run alongside it.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    #[test]
+    fn profile_counts_loop_body_opcodes_most() {
+        let (mut vm, _) = vm();
+        vm.is_profile = true;
+        let source = "let i = 0; while (i < 100) { i += 1; } print i;";
+        vm.interpret(compile(source).unwrap()).unwrap();
+
+        let mut counts = vm.profile.iter().collect::<Vec<_>>();
+        counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        assert_eq!(counts[0], (&"LOAD", &203));
+        assert_eq!(vm.profile["LESS"], 101);
+        assert_eq!(vm.profile["ADD_I_GLOBAL"], 100);
+        assert_eq!(vm.profile["PRINT"], 1);
+    }
+
+    #[test]
+    fn globals_read_and_undefined_ones_error_at_their_position() {
+        let (mut vm, out) = vm();
+        let source = "let a = 1;\nprint a;\nprint b;";
+        let err = vm.interpret(compile(source).unwrap()).unwrap_err();
+
+        assert_eq!(out.take(), "1\n");
+        match err {
+            LoxError::RuntimeError {
+                position,
+                lexeme,
+                msg,
+                ..
+            } => {
+                assert_eq!(position, (3, 6));
+                assert_eq!(*lexeme, "b");
+                assert!(msg.starts_with("Undefined variable `b`."));
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    // A timing of GetGlobal and SetGlobal in a tight loop, run with
+    // `cargo test --release global_get_and_set -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn global_get_and_set_in_a_tight_loop() {
+        let source =
+            "let i = 0; let sum = 0; while (i < 1000000) { sum = sum + i; i = i + 1; } print sum;";
+        let (mut vm, out) = vm();
+        let function = compile(source).unwrap();
+
+        let start = std::time::Instant::now();
+        vm.interpret(function).unwrap();
+        println!("1000000 iterations in {:?}", start.elapsed());
+        assert_eq!(out.take(), "499999500000\n");
+    }
+
+    #[test]
+    fn failed_repl_line_rolls_back_its_globals() {
+        let (mut vm, out) = vm();
+        vm.is_repl = true;
+
+        vm.interpret(compile("let a = 1;").unwrap()).unwrap();
+        let line = compile("let b = 2; a = 3; print nil + 1;").unwrap();
+        assert!(vm.interpret(line).is_err());
+
+        vm.interpret(compile("print a;").unwrap()).unwrap();
+        assert!(out.take().ends_with("1\n"));
+        assert!(vm.interpret(compile("print b;").unwrap()).is_err());
+    }
+
+    #[test]
+    fn failed_repl_line_keeps_changes_made_inside_values() {
+        let (mut vm, out) = vm();
+        vm.is_repl = true;
+
+        let setup = r#"
+            class P {}
+            let a = [1]; let m = {"k": 1}; let p = P(); p.x = 1; let sb = sb_new();
+        "#;
+        vm.interpret(compile(setup).unwrap()).unwrap();
+        let line = r#"a[0] = 2; m["k"] = 2; p.x = 2; sb_push(sb, "2"); a = nil; print nil + 1;"#;
+        assert!(vm.interpret(compile(line).unwrap()).is_err());
+
+        // `a` is bound to the same array again, which still holds the 2
+        vm.interpret(compile("print a, m, p.x, sb_build(sb);").unwrap())
+            .unwrap();
+        assert!(out.take().ends_with("[2] {k: 2} 2 2\n"));
+    }
+
+    #[test]
+    fn arithmetic_out_of_range_is_an_error() {
+        let big = format!("1{}", "0".repeat(308));
+        let err = run(&format!("print {big} * 10;")).unwrap_err();
+        assert!(err.starts_with("Numeric result out of range."));
+        let err = run(&format!("print -{big} - {big};")).unwrap_err();
+        assert!(err.starts_with("Numeric result out of range."));
+        assert_eq!(run(&format!("print {big} / 10 > 1;")), Ok("true\n".into()));
+
+        // no way of making a NaN either
+        assert!(run("print 0 / 0;")
+            .unwrap_err()
+            .starts_with("divisor cannot be 0."));
+        assert!(run("print 0 % 0;")
+            .unwrap_err()
+            .starts_with("divisor cannot be 0."));
+        let err = run("print sqrt(-1);").unwrap_err();
+        assert!(err.starts_with("`sqrt` expects a non-negative number, but got `-1`."));
+
+        // nor a literal that is too big, which would otherwise read as inf
+        let err = run(&format!("print 1{};", "0".repeat(400))).unwrap_err();
+        assert_eq!(err, "Numeric result out of range.");
+    }
+
+    #[test]
+    fn error_in_nested_calls_leaves_no_frames_behind() {
+        let (mut vm, out) = vm();
+        let source = "func f() { return nil + 1; } func g() { return f(); } g();";
+        assert!(vm.interpret(compile(source).unwrap()).is_err());
+        assert!(vm.frames.is_empty());
+        assert!(vm.stack.is_empty());
+
+        vm.interpret(compile("print 1;").unwrap()).unwrap();
+        assert_eq!(out.take(), "1\n");
+    }
+
+    #[test]
+    fn compound_mod_matches_mod_with_negative_operands() {
+        let global = "let x = -7; x %= 3; print x; print -7 % 3;";
+        assert_eq!(run(global), Ok("-1\n-1\n".into()));
+        let local = "{ let y = 7; y %= -3; print y; print 7 % -3; }";
+        assert_eq!(run(local), Ok("1\n1\n".into()));
+    }
+
+    #[test]
+    fn bare_and_valued_returns_both_work() {
+        let source = "func f() { return; } func g(x) { return x; } print f(); print g(2);";
+        assert_eq!(run(source), Ok("nil\n2\n".into()));
+    }
+
+    #[test]
+    fn repl_results_are_kept_in_underscore_globals() {
+        let (mut vm, _) = vm();
+        vm.is_repl = true;
+
+        let value = vm.interpret(compile_line("1 + 2;").unwrap()).unwrap();
+        vm.record_result(value);
+        let value = vm.interpret(compile_line("_ * 2;").unwrap()).unwrap();
+        vm.record_result(value);
+
+        let value = vm.interpret(compile_line("_ * 10 + _1;").unwrap()).unwrap();
+        assert_eq!(value.to_string(), "63");
+    }
+
+    #[test]
+    fn budget_stops_an_infinite_loop() {
+        let (mut vm, out) = vm();
+        vm.set_budget(Some(1000));
+
+        let err = vm.interpret(compile("while (true) {}").unwrap());
+        assert!(message(&err.unwrap_err()).starts_with("Execution limit exceeded."));
+
+        // the budget is per call, so the next program runs normally
+        vm.interpret(compile("print 1;").unwrap()).unwrap();
+        assert_eq!(out.take(), "1\n");
+    }
+
+    #[test]
+    fn len_truthy_mode_makes_zero_and_empty_values_falsy() {
+        let source = r#"print 0 ? 1 : 2; print 3 ? 1 : 2; print "" ? 1 : 2; print "a" ? 1 : 2;"#;
+        assert_eq!(run(source), Ok("1\n1\n1\n1\n".into()));
+
+        let (mut vm, out) = vm();
+        vm.is_len_truthy = true;
+        vm.interpret(compile(source).unwrap()).unwrap();
+        assert_eq!(out.take(), "2\n1\n2\n1\n");
+    }
+
+    #[test]
+    fn function_returned_by_a_call_can_be_called_directly() {
+        // there are no closures yet, so the returned lambda reads a global
+        // instead of a parameter of `makeAdder`
+        let source = "
+            let x = 3;
+            func makeAdder() { return func(y) { return x + y; }; }
+            func twice() { return makeAdder; }
+            print makeAdder()(4);
+            print twice()()(5);
+        ";
+        assert_eq!(run(source), Ok("7\n8\n".into()));
+    }
+
+    #[test]
+    fn printing_allocates_nothing_per_value() {
+        let allocations = |count: usize| {
+            let (mut vm, _) = vm();
+            vm.out = BufWriter::new(Box::new(io::sink()));
+            let source =
+                format!("let i = 0; while (i < {count}) {{ print i, \"a\", nil; i += 1; }}");
+            let function = compile(&source).unwrap();
+
+            let before = ALLOCATIONS.with(Cell::get);
+            vm.interpret(function).unwrap();
+            ALLOCATIONS.with(Cell::get) - before
+        };
+
+        assert_eq!(allocations(2000), allocations(1000));
+    }
+
+    #[test]
+    fn prints_reach_the_output_in_one_write_per_buffer() {
+        #[derive(Clone, Default)]
+        struct Writes(Rc<Cell<usize>>);
+
+        impl Write for Writes {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.set(self.0.get() + 1);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let writes = Writes::default();
+        let (mut vm, _) = vm();
+        vm.out = BufWriter::new(Box::new(writes.clone()));
+        let source = "let i = 0; while (i < 1000) { print i; i += 1; }";
+        vm.interpret(compile(source).unwrap()).unwrap();
+
+        // the 3890 bytes printed fit in the buffer, and go out at the end
+        assert_eq!(writes.0.get(), 1);
+    }
+
+    #[test]
+    fn buffered_prints_are_all_written_when_interpret_returns() {
+        let source = "let i = 0; while (i < 5000) { print i; print -i / 2; i = i + 1; }";
+        let expected = (0..5000)
+            .map(|i| format!("{}\n{}\n", i, -(i as f64) / 2.0))
+            .collect::<String>();
+        assert_eq!(run(source), Ok(expected));
+
+        // output printed before an error still comes out
+        let (mut vm, out) = vm();
+        let result = vm.interpret(compile("print \"a\"; print nil + 1;").unwrap());
+        assert!(result.is_err());
+        assert_eq!(out.take(), "a\n");
+    }
+
+    #[test]
+    fn coverage_marks_an_untaken_else_as_not_executed() {
+        let (mut vm, _) = vm();
+        vm.coverage = Some(Default::default());
+        let source = "let a = 1;\nif (a > 0)\n  print a;\nelse\n  print -a;\nprint a;";
+        vm.interpret(compile(source).unwrap()).unwrap();
+
+        let coverage = vm.coverage.unwrap();
+        assert_eq!(coverage.get(&3), Some(&true));
+        assert_eq!(coverage.get(&5), Some(&false));
+        assert_eq!(coverage.get(&6), Some(&true));
+    }
+
+    #[test]
+    fn lambda_arity_error_names_where_it_was_defined() {
+        let source = "let add = func(a, b) { return a + b; };\nadd(1);";
+        let err = run(source).unwrap_err();
+        assert_eq!(
+            err,
+            "Expect 2 arguments but got 1 when calling lambda defined at [1,10]."
+        );
+        assert!(!err.contains('$'));
+    }
+
+    #[test]
+    fn modulo_rejects_non_integer_operands() {
+        assert_eq!(run("print 5 % 2, -5 % 2;"), Ok("1 -1\n".into()));
+        assert!(run("print 2.5 % 2;")
+            .unwrap_err()
+            .starts_with("Operand must be an integer."));
+        assert!(run("print 5 % 1.5;")
+            .unwrap_err()
+            .starts_with("Operand must be an integer."));
+    }
+
+    #[test]
+    fn fused_local_operands_give_the_same_result_with_fewer_opcodes() {
+        // a grouped operand isn't a plain variable, so it isn't fused
+        let run_counted = |body: &str| {
+            let (mut vm, out) = vm();
+            vm.is_profile = true;
+            let source = format!(
+                "{{ let sum = 0; let i = 0; let step = 1; while (i < 1000) {{ {body} }} print sum; }}"
+            );
+            vm.interpret(compile(&source).unwrap()).unwrap();
+            (out.take(), vm.profile.values().sum::<usize>(), vm.profile)
+        };
+
+        let (fused, fused_count, profile) = run_counted("sum = sum + i; i = i + step;");
+        let (plain, plain_count, _) = run_counted("sum = sum + (i); i = i + (step);");
+        assert_eq!(fused, "499500\n");
+        assert_eq!(fused, plain);
+        assert_eq!(profile["ADD_LOCAL"], 2000);
+        assert!(fused_count < plain_count);
+
+        let err = run("{ let s = \"a\"; print 1 - s; }").unwrap_err();
+        assert!(err.starts_with("Operands must be two numbers"));
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_numbers() {
+        let run_seeded = |seed| {
+            let (mut vm, out) = vm();
+            vm.rng = mix_seed(seed);
+            let source = "let i = 0; while (i < 5) { print random(); i = i + 1; }";
+            vm.interpret(compile(source).unwrap()).unwrap();
+            out.take()
+        };
+
+        assert_eq!(run_seeded(42), run_seeded(42));
+        assert_ne!(run_seeded(42), run_seeded(43));
+        assert_ne!(run_seeded(0), run_seeded(1));
+    }
+
+    #[test]
+    fn failed_assert_shows_the_asserted_expression() {
+        let err = run("assert 1 == 2;").unwrap_err();
+        assert!(err.contains("1 == 2"), "{err}");
+        assert_eq!(run("assert 1 + 1 == 2; print 1;"), Ok("1\n".into()));
+    }
+
+    #[test]
+    fn classes_construct_instances_with_methods() {
+        let source = "
+            class Point {
+                __init__(x, y) { self.x = x; self.y = y; }
+                sum() { return self.x + self.y; }
+                #[static] origin() { return Point(0, 0); }
+            }
+            let p = Point(1, 2);
+            print Point, p, p.sum(), Point.origin().sum();
+        ";
+        assert_eq!(
+            run(source),
+            Ok("<class Point> <Point instance> 3 0\n".into())
+        );
+
+        let err = run("class A {} print A().z;").unwrap_err();
+        assert_eq!(err, "Undefined property `z`.");
+    }
+
+    #[test]
+    fn static_fields_are_read_from_the_class() {
+        let source = "
+            class Circle {
+                #[static] let PI = 3.14, UNIT;
+                __init__(r) { self.r = r; }
+                area() { return Circle.PI * self.r * self.r; }
+            }
+            class Ring extend Circle {}
+            print Circle.PI, Circle.UNIT, Circle(2).area(), Ring.PI;
+        ";
+        assert_eq!(run(source), Ok("3.14 nil 12.56 3.14\n".into()));
+
+        let source = "{ class A { #[static] let N = 1 + 1; } print A.N; }";
+        assert_eq!(run(source), Ok("2\n".into()));
+
+        let err = run("class A { #[static] let N = 1; } print A.M;").unwrap_err();
+        assert_eq!(err, "Undefined property `M`.");
+        // a static field is not a field of the instances
+        let err = run("class A { #[static] let N = 1; } print A().N;").unwrap_err();
+        assert_eq!(err, "Undefined property `N`.");
+    }
+
+    #[test]
+    fn field_writes_in_methods_persist_between_calls() {
+        let source = "
+            class Counter {
+                __init__() { self.count = 0; }
+                inc() { self.count = self.count + 1; }
+            }
+            let c = Counter();
+            c.inc();
+            c.inc();
+            print c.count;
+        ";
+        assert_eq!(run(source), Ok("2\n".into()));
+    }
+
+    #[test]
+    fn operators_on_instances_call_their_methods() {
+        let vector = "
+            class Vector {
+                __init__(x, y) { self.x = x; self.y = y; }
+                __add__(other) { return Vector(self.x + other.x, self.y + other.y); }
+                __eq__(other) { return self.x == other.x and self.y == other.y; }
+                __lt__(other) { return self.x < other.x; }
+            }
+        ";
+        let source = format!(
+            "{vector}
+            let v1 = Vector(1, 2);
+            let v2 = Vector(3, 4);
+            let v3 = v1 + v2;
+            print v3.x, v3.y;
+            print v1 == Vector(1, 2), v1 != Vector(1, 2), v1 == v2;
+            print v1 < v2, v1 >= v2;
+            func sum(a, b) {{ let c = a + b; return c.x; }}
+            print sum(v2, v1);"
+        );
+        assert_eq!(
+            run(&source),
+            Ok("4 6\ntrue false false\ntrue false\n4\n".into())
+        );
+
+        // without a method an instance is an ordinary operand
+        let err = run(&format!("{vector} Vector(1, 2) - Vector(3, 4);")).unwrap_err();
+        assert!(err.starts_with("Operands must be two numbers"));
+        let err = run("class A { __add__() { return 1; } } A() + 1;").unwrap_err();
+        assert!(err.starts_with("Expect 0 arguments but got 1"));
+    }
+
+    #[test]
+    fn frozen_instance_can_be_read_but_not_set() {
+        let source = "
+            class P { __init__(x) { self.x = x; } get() { return self.x; } }
+            let p = freeze(P(1));
+            print p.x, p.get();
+        ";
+        assert_eq!(run(source), Ok("1 1\n".into()));
+
+        let source = "class P {}\nlet p = P();\np.x = 1;\nfreeze(p);\np.x = 2;";
+        let (mut vm, _) = vm();
+        match vm.interpret(compile(source).unwrap()) {
+            Err(LoxError::RuntimeError { position, msg, .. }) => {
+                assert_eq!(position, (5, 2));
+                assert_eq!(msg, "Cannot modify frozen instance.");
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+        let err = run("class P { set() { self.y = 1; } } freeze(P()).set();").unwrap_err();
+        assert!(err.starts_with("Cannot modify frozen instance."));
+        assert_eq!(
+            run("freeze(1);").unwrap_err(),
+            "Expect an instance but got `1`."
+        );
+    }
+
+    #[test]
+    fn super_calls_the_superclass_method_on_the_same_instance() {
+        let source = r#"
+            class A { name() { return "A"; } hi() { return "hi " + self.name(); } }
+            class B extend A { name() { return "B"; } hi() { return super.hi() + "!"; } }
+            print B().hi();
+        "#;
+        assert_eq!(run(source), Ok("hi B!\n".into()));
+
+        let err = run("class A { m() { return super.m(); } }").unwrap_err();
+        assert!(err.starts_with("Keyword `super` can only be used in methods"));
+    }
+
+    #[test]
+    fn super_reads_methods_as_values_and_fields_of_self() {
+        let source = r#"
+            class A {
+                __init__(x) { self.x = x; self.kind = "A"; }
+                name() { return "A" + str(self.x); }
+            }
+            class B extend A {
+                __init__(x) { super.__init__(x * 2); self.kind = "B"; }
+                name() { let f = super.name; return f() + "/" + super.kind; }
+            }
+            let b = B(1);
+            print b.name(), b.x;
+        "#;
+        assert_eq!(run(source), Ok("A2/B 2\n".into()));
+
+        let source = "class A {} class B extend A { m() { return super.nope; } } B().m();";
+        let err = run(source).unwrap_err();
+        assert!(err.starts_with("Undefined superclass property `nope`."));
+    }
+
+    #[test]
+    fn subclasses_inherit_methods_through_every_level() {
+        let source = "
+            class A { f() { return 1; } }
+            class B extend A { g() { return 2; } }
+            class C extend B {}
+            let c = C();
+            print c.f() + c.g();
+        ";
+        assert_eq!(run(source), Ok("3\n".into()));
+
+        let err = run("let x = 1; class B extend x {}").unwrap_err();
+        assert_eq!(err, "Superclass of `B` must be a class.");
+        let err = run("class B extend Nope {}").unwrap_err();
+        assert_eq!(err, "Undefined variable `Nope`.");
+    }
+
+    #[test]
+    fn compound_mod_on_a_local_takes_the_remainder() {
+        let source = "{ let a = 17; a %= 5; let b = 10; { b %= 4; } print a, b; }";
+        assert_eq!(run(source), Ok("2 2\n".into()));
+        assert_eq!(
+            run("{ let y = 7; y %= 0; }"),
+            Err("divisor cannot be 0.".into())
+        );
+    }
+
+    #[test]
+    fn compound_divide_by_zero_reports_its_operator() {
+        let (mut vm, _) = vm();
+        match vm.interpret(compile("let x = 7; x /= 0;").unwrap()) {
+            Err(LoxError::RuntimeError { lexeme, msg, .. }) => {
+                assert_eq!(*lexeme, "/=");
+                assert_eq!(msg, "divisor cannot be 0.");
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn backtrace_labels_each_frame_by_its_own_function() {
+        let source =
+            "func outer() {\n  let l = func() { return nil + 1; };\n  return l();\n}\nouter();";
+        let err = run(source).unwrap_err();
+        let mut lines = err.lines().skip(1);
+        assert_eq!(lines.next(), Some("[ 3, 2] Error in function `outer`"));
+        assert_eq!(lines.next(), Some("[ 5, 7] Error in function `__main__`"));
+
+        let source = "func f() { return nil + 1; }\nlet l = func() { return f(); };\nl();";
+        let err = run(source).unwrap_err();
+        let mut lines = err.lines().skip(1);
+        assert_eq!(lines.next(), Some("[ 2,17] Error in Lambda"));
+        assert_eq!(lines.next(), Some("[ 3, 3] Error in function `__main__`"));
+    }
+}
@@ -16,12 +16,14 @@ pub enum LoxError {
     },
     ParseError {
         position: (usize, usize),
+        end: (usize, usize),
         lexeme: Rc<String>,
         token_type: TokenType,
         msg: String,
     },
     RuntimeError {
         position: (usize, usize),
+        end: (usize, usize),
         lexeme: Rc<String>,
         msg: String,
     },
@@ -34,7 +36,8 @@ impl LoxError {
     pub fn create_runtime_error(token: &Token, msg: String) -> Self {
         Self::RuntimeError {
             position: token.position,
-            lexeme: token.lexeme.clone(),
+            end: token.end,
+            lexeme: token.display_lexeme(),
             msg,
         }
     }
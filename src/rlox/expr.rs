@@ -70,8 +70,13 @@ expr! {
     TernaryExpression { cmp: Box<Expression>, true_value: Box<Expression>, false_value: Box<Expression> },
     UnaryExpression { op: Token, right: Box<Expression> },
     VariableExpression { name: Token },
-    LambdaExpression { params: Vec<Token>, body: Vec<Statement> },
-    OperateAndAssignExpression { name: Token, op: Token, value: Box<Expression> }
+    LambdaExpression { keyword: Token, params: Vec<Token>, body: Vec<Statement> },
+    OperateAndAssignExpression { name: Token, op: Token, value: Box<Expression> },
+    MultiAssignExpression { names: Vec<Token>, equal: Token, values: Vec<Expression> },
+    ArrayExpression { bracket: Token, elements: Vec<Expression> },
+    MapExpression { brace: Token, entries: Vec<(Expression, Expression)> },
+    IndexExpression { object: Box<Expression>, bracket: Token, index: Box<Expression> },
+    IndexSetExpression { object: Box<Expression>, bracket: Token, index: Box<Expression>, value: Box<Expression> }
 }
 
 impl Display for Expression {
@@ -90,11 +95,33 @@ impl Display for Expression {
                     .join(", ")
             ),
             Expression::GetExpression(g) => write!(f, "{}.{}", g.object, g.name),
+            Expression::ArrayExpression(a) => write!(
+                f,
+                "[{}]",
+                a.elements
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Expression::MapExpression(m) => write!(
+                f,
+                "{{{}}}",
+                m.entries
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", k, v))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Expression::IndexExpression(i) => write!(f, "{}[{}]", i.object, i.index),
+            Expression::IndexSetExpression(i) => {
+                write!(f, "{}[{}] = {}", i.object, i.index, i.value)
+            }
             Expression::GroupingExpression(g) => write!(f, "({})", g.expression),
             Expression::LiteralExpression(l) => write!(f, "{}", l.value),
             Expression::LogicalExpression(l) => write!(f, "{} {} {}", l.left, l.op, l.right),
             Expression::SetExpression(s) => write!(f, "{}.{} = {}", s.object, s.name, s.value),
-            Expression::SuperExpression(_) => todo!(),
+            Expression::SuperExpression(s) => write!(f, "super.{}", s.method),
             Expression::SelfExpression(t) => write!(f, "{}", t.keyword.lexeme),
             Expression::TernaryExpression(t) => {
                 write!(f, "{} ? {} : {}", t.cmp, t.true_value, t.false_value)
@@ -113,6 +140,20 @@ impl Display for Expression {
             Expression::OperateAndAssignExpression(s) => {
                 write!(f, "{} {} {}", s.name.lexeme, s.op.lexeme, s.value)
             }
+            Expression::MultiAssignExpression(m) => write!(
+                f,
+                "({}) = ({})",
+                m.names
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                m.values
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
         }
     }
 }
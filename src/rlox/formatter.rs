@@ -0,0 +1,679 @@
+use super::{
+    error::LoxError,
+    expr::{Expression, Visitor as ExprVisitor},
+    stmt::{Statement, Visitor as StmtVisitor},
+    types::{FuncType, Literal, TokenType},
+};
+
+const INDENT: &str = "    ";
+
+/// Turns parsed statements back into canonically formatted source: four
+/// space indentation, one statement per line and single spaces around
+/// binary operators. Comments parsed from a comment-keeping scanner are
+/// kept. Formatting already formatted source is a no-op.
+#[derive(Default)]
+pub struct Formatter {
+    depth: usize,
+}
+
+impl Formatter {
+    pub fn format(&mut self, statements: &[Statement]) -> Result<String, LoxError> {
+        let mut lines = vec![];
+        self.format_statements(statements, &mut lines)?;
+        Ok(lines.join("\n") + "\n")
+    }
+
+    /// Formats statements one per line, except that a trailing comment stays
+    /// at the end of the line before it.
+    fn format_statements(
+        &mut self,
+        statements: &[Statement],
+        lines: &mut Vec<String>,
+    ) -> Result<(), LoxError> {
+        for stmt in statements {
+            match (stmt, lines.last_mut()) {
+                (Statement::CommentStatement(comment), Some(line)) if comment.trailing => {
+                    line.push(' ');
+                    line.push_str(&comment.token.lexeme);
+                }
+                _ => lines.push(self.format_statement(stmt)?),
+            }
+        }
+        Ok(())
+    }
+
+    fn indent(&self) -> String {
+        INDENT.repeat(self.depth)
+    }
+
+    fn format_expression(&mut self, expr: &Expression) -> Result<String, LoxError> {
+        expr.accept(self)
+    }
+
+    fn format_statement(&mut self, stmt: &Statement) -> Result<String, LoxError> {
+        Ok(format!("{}{}", self.indent(), stmt.accept(self)?))
+    }
+
+    fn format_block(&mut self, statements: &[Statement]) -> Result<String, LoxError> {
+        if statements.is_empty() {
+            return Ok("{}".into());
+        }
+
+        self.depth += 1;
+        let mut lines = vec!["{".to_string()];
+        let result = self.format_statements(statements, &mut lines);
+        self.depth -= 1;
+        result?;
+        lines.push(format!("{}}}", self.indent()));
+
+        Ok(lines.join("\n"))
+    }
+
+    // Like `format_block`, except that the only `let` in a class body is a
+    // static field, which gets its modifier back.
+    fn format_class_body(&mut self, members: &[Statement]) -> Result<String, LoxError> {
+        if members.is_empty() {
+            return Ok("{}".into());
+        }
+
+        self.depth += 1;
+        let mut lines = vec!["{".to_string()];
+        let result = members.iter().try_for_each(|member| match member {
+            Statement::MultiVarStatement(_) => {
+                lines.push(format!(
+                    "{}#[static] {}",
+                    self.indent(),
+                    member.accept(self)?
+                ));
+                Ok(())
+            }
+            member => self.format_statements(std::slice::from_ref(member), &mut lines),
+        });
+        self.depth -= 1;
+        result?;
+        lines.push(format!("{}}}", self.indent()));
+
+        Ok(lines.join("\n"))
+    }
+
+    // The body of an `if`/`while` stays on the line of its header when it is
+    // a block and moves to its own, deeper indented line otherwise. A loop
+    // guard is parsed as an `if` around the body and goes back to `when`.
+    fn format_body(&mut self, body: &Statement) -> Result<String, LoxError> {
+        match body {
+            Statement::BlockStatement(block) => {
+                return Ok(format!(" {}", self.format_block(&block.statements)?));
+            }
+            Statement::BranchStatement(branch) if branch.keyword.token_type == TokenType::When => {
+                return Ok(format!(
+                    " when ({}){}",
+                    self.format_expression(&branch.condition)?,
+                    self.format_body(&branch.then_branch)?
+                ));
+            }
+            _ => (),
+        }
+
+        self.depth += 1;
+        let body = self.format_statement(body);
+        self.depth -= 1;
+        Ok(format!("\n{}", body?))
+    }
+
+    fn format_function(
+        &mut self,
+        params: &[super::token::Token],
+        body: &[Statement],
+    ) -> Result<String, LoxError> {
+        Ok(format!(
+            "({}) {}",
+            params
+                .iter()
+                .map(|p| p.lexeme.to_string())
+                .collect::<Vec<String>>()
+                .join(", "),
+            self.format_block(body)?
+        ))
+    }
+
+    fn format_for(
+        &mut self,
+        initializer: Option<&Statement>,
+        while_statement: &super::stmt::WhileStatement,
+    ) -> Result<String, LoxError> {
+        let initializer = match initializer {
+            Some(init) => init.accept(self)?,
+            None => ";".into(),
+        };
+        let increment = match &while_statement.increment {
+            Some(incr) => match incr.as_ref() {
+                Statement::ExpressionStatement(e) => self.format_expression(&e.expression)?,
+                Statement::BlockStatement(block) => block
+                    .statements
+                    .iter()
+                    .map(|statement| match statement {
+                        Statement::ExpressionStatement(e) => self.format_expression(&e.expression),
+                        statement => statement.accept(self),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+                    .join(", "),
+                incr => incr.accept(self)?,
+            },
+            None => "".into(),
+        };
+        let body = match while_statement.body.as_ref() {
+            Statement::BlockStatement(block) if block.statements.len() == 1 => &block.statements[0],
+            body => body,
+        };
+
+        Ok(format!(
+            "for ({} {}; {}){}",
+            initializer,
+            self.format_expression(&while_statement.condition)?,
+            increment,
+            self.format_body(body)?
+        ))
+    }
+}
+
+impl ExprVisitor<String, LoxError> for Formatter {
+    fn visit_multi_assign_expression(
+        &mut self,
+        multi_assign_expression: &super::expr::MultiAssignExpression,
+    ) -> Result<String, LoxError> {
+        let names = multi_assign_expression
+            .names
+            .iter()
+            .map(|name| name.lexeme.to_string())
+            .collect::<Vec<_>>();
+        let values = multi_assign_expression
+            .values
+            .iter()
+            .map(|value| self.format_expression(value))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(format!("({}) = ({})", names.join(", "), values.join(", ")))
+    }
+
+    fn visit_assign_expression(
+        &mut self,
+        assign_expression: &super::expr::AssignExpression,
+    ) -> Result<String, LoxError> {
+        Ok(format!(
+            "{} = {}",
+            assign_expression.name.lexeme,
+            self.format_expression(&assign_expression.value)?
+        ))
+    }
+
+    fn visit_binary_expression(
+        &mut self,
+        binary_expression: &super::expr::BinaryExpression,
+    ) -> Result<String, LoxError> {
+        Ok(format!(
+            "{} {} {}",
+            self.format_expression(&binary_expression.left)?,
+            binary_expression.op,
+            self.format_expression(&binary_expression.right)?
+        ))
+    }
+
+    fn visit_call_expression(
+        &mut self,
+        call_expression: &super::expr::CallExpression,
+    ) -> Result<String, LoxError> {
+        let mut args = vec![];
+        for arg in &call_expression.arguments {
+            args.push(self.format_expression(arg)?);
+        }
+        Ok(format!(
+            "{}({})",
+            self.format_expression(&call_expression.callee)?,
+            args.join(", ")
+        ))
+    }
+
+    fn visit_get_expression(
+        &mut self,
+        get_expression: &super::expr::GetExpression,
+    ) -> Result<String, LoxError> {
+        Ok(format!(
+            "{}.{}",
+            self.format_expression(&get_expression.object)?,
+            get_expression.name.lexeme
+        ))
+    }
+
+    fn visit_grouping_expression(
+        &mut self,
+        grouping_expression: &super::expr::GroupingExpression,
+    ) -> Result<String, LoxError> {
+        Ok(format!(
+            "({})",
+            self.format_expression(&grouping_expression.expression)?
+        ))
+    }
+
+    fn visit_literal_expression(
+        &mut self,
+        literal_expression: &super::expr::LiteralExpression,
+    ) -> Result<String, LoxError> {
+        Ok(match &literal_expression.value {
+            Literal::String(_) => literal_expression.token.lexeme.to_string(),
+            Literal::Number(_) if literal_expression.token.token_type == TokenType::Number => {
+                literal_expression.token.lexeme.to_string()
+            }
+            value => value.to_string(),
+        })
+    }
+
+    fn visit_logical_expression(
+        &mut self,
+        logical_expression: &super::expr::LogicalExpression,
+    ) -> Result<String, LoxError> {
+        Ok(format!(
+            "{} {} {}",
+            self.format_expression(&logical_expression.left)?,
+            logical_expression.op,
+            self.format_expression(&logical_expression.right)?
+        ))
+    }
+
+    fn visit_array_expression(
+        &mut self,
+        array_expression: &super::expr::ArrayExpression,
+    ) -> Result<String, LoxError> {
+        let elements = array_expression
+            .elements
+            .iter()
+            .map(|element| self.format_expression(element))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(format!("[{}]", elements.join(", ")))
+    }
+
+    fn visit_map_expression(
+        &mut self,
+        map_expression: &super::expr::MapExpression,
+    ) -> Result<String, LoxError> {
+        let entries = map_expression
+            .entries
+            .iter()
+            .map(|(key, value)| {
+                Ok(format!(
+                    "{}: {}",
+                    self.format_expression(key)?,
+                    self.format_expression(value)?
+                ))
+            })
+            .collect::<Result<Vec<_>, LoxError>>()?;
+        Ok(format!("{{{}}}", entries.join(", ")))
+    }
+
+    fn visit_index_expression(
+        &mut self,
+        index_expression: &super::expr::IndexExpression,
+    ) -> Result<String, LoxError> {
+        Ok(format!(
+            "{}[{}]",
+            self.format_expression(&index_expression.object)?,
+            self.format_expression(&index_expression.index)?
+        ))
+    }
+
+    fn visit_index_set_expression(
+        &mut self,
+        index_set_expression: &super::expr::IndexSetExpression,
+    ) -> Result<String, LoxError> {
+        Ok(format!(
+            "{}[{}] = {}",
+            self.format_expression(&index_set_expression.object)?,
+            self.format_expression(&index_set_expression.index)?,
+            self.format_expression(&index_set_expression.value)?
+        ))
+    }
+
+    fn visit_set_expression(
+        &mut self,
+        set_expression: &super::expr::SetExpression,
+    ) -> Result<String, LoxError> {
+        Ok(format!(
+            "{}.{} = {}",
+            self.format_expression(&set_expression.object)?,
+            set_expression.name.lexeme,
+            self.format_expression(&set_expression.value)?
+        ))
+    }
+
+    fn visit_super_expression(
+        &mut self,
+        super_expression: &super::expr::SuperExpression,
+    ) -> Result<String, LoxError> {
+        Ok(format!("super.{}", super_expression.method.lexeme))
+    }
+
+    fn visit_self_expression(
+        &mut self,
+        _self_expression: &super::expr::SelfExpression,
+    ) -> Result<String, LoxError> {
+        Ok("self".into())
+    }
+
+    fn visit_ternary_expression(
+        &mut self,
+        ternary_expression: &super::expr::TernaryExpression,
+    ) -> Result<String, LoxError> {
+        Ok(format!(
+            "{} ? {} : {}",
+            self.format_expression(&ternary_expression.cmp)?,
+            self.format_expression(&ternary_expression.true_value)?,
+            self.format_expression(&ternary_expression.false_value)?
+        ))
+    }
+
+    fn visit_unary_expression(
+        &mut self,
+        unary_expression: &super::expr::UnaryExpression,
+    ) -> Result<String, LoxError> {
+        Ok(format!(
+            "{}{}",
+            unary_expression.op,
+            self.format_expression(&unary_expression.right)?
+        ))
+    }
+
+    fn visit_variable_expression(
+        &mut self,
+        variable_expression: &super::expr::VariableExpression,
+    ) -> Result<String, LoxError> {
+        Ok(variable_expression.name.lexeme.to_string())
+    }
+
+    fn visit_lambda_expression(
+        &mut self,
+        lambda_expression: &super::expr::LambdaExpression,
+    ) -> Result<String, LoxError> {
+        Ok(format!(
+            "func {}",
+            self.format_function(&lambda_expression.params, &lambda_expression.body)?
+        ))
+    }
+
+    fn visit_operate_and_assign_expression(
+        &mut self,
+        operate_and_assign_expression: &super::expr::OperateAndAssignExpression,
+    ) -> Result<String, LoxError> {
+        Ok(format!(
+            "{} {} {}",
+            operate_and_assign_expression.name.lexeme,
+            operate_and_assign_expression.op,
+            self.format_expression(&operate_and_assign_expression.value)?
+        ))
+    }
+}
+
+impl StmtVisitor<String, LoxError> for Formatter {
+    fn visit_expression_statement(
+        &mut self,
+        expression_statement: &super::stmt::ExpressionStatement,
+    ) -> Result<String, LoxError> {
+        let expr = self.format_expression(&expression_statement.expression)?;
+        // A lambda declared as a statement isn't followed by a `;`.
+        if let Expression::LambdaExpression(_) = expression_statement.expression {
+            Ok(expr)
+        } else {
+            Ok(format!("{};", expr))
+        }
+    }
+
+    fn visit_print_statement(
+        &mut self,
+        print_statement: &super::stmt::PrintStatement,
+    ) -> Result<String, LoxError> {
+        let values = print_statement
+            .expressions
+            .iter()
+            .map(|expression| self.format_expression(expression))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(format!("print {};", values.join(", ")))
+    }
+
+    fn visit_assert_statement(
+        &mut self,
+        assert_statement: &super::stmt::AssertStatement,
+    ) -> Result<String, LoxError> {
+        Ok(format!(
+            "assert {};",
+            self.format_expression(&assert_statement.condition)?
+        ))
+    }
+
+    fn visit_var_statement(
+        &mut self,
+        var_statement: &super::stmt::VarStatement,
+    ) -> Result<String, LoxError> {
+        match &var_statement.initializer {
+            Some(init) => Ok(format!(
+                "{} = {}",
+                var_statement.name.lexeme,
+                self.format_expression(init)?
+            )),
+            None => Ok(var_statement.name.lexeme.to_string()),
+        }
+    }
+
+    fn visit_multi_var_statement(
+        &mut self,
+        multi_var_statement: &super::stmt::MultiVarStatement,
+    ) -> Result<String, LoxError> {
+        let mut vars = vec![];
+        for var in &multi_var_statement.vars {
+            vars.push(var.accept(self)?);
+        }
+        Ok(format!("let {};", vars.join(", ")))
+    }
+
+    fn visit_block_statement(
+        &mut self,
+        block_statement: &super::stmt::BlockStatement,
+    ) -> Result<String, LoxError> {
+        // `for` loops are desugared into an optional initializer followed by
+        // a `while` carrying the increment; they are printed back as `for`.
+        match block_statement.statements.as_slice() {
+            [init, Statement::WhileStatement(w)] if w.increment.is_some() => {
+                self.format_for(Some(init), w)
+            }
+            statements => self.format_block(statements),
+        }
+    }
+
+    fn visit_branch_statement(
+        &mut self,
+        branch_statement: &super::stmt::BranchStatement,
+    ) -> Result<String, LoxError> {
+        let mut branch = format!(
+            "if ({}){}",
+            self.format_expression(&branch_statement.condition)?,
+            self.format_body(&branch_statement.then_branch)?
+        );
+
+        if let Some(else_branch) = &branch_statement.else_branch {
+            if let Statement::BlockStatement(_) = branch_statement.then_branch.as_ref() {
+                branch.push(' ');
+            } else {
+                branch.push('\n');
+                branch.push_str(&self.indent());
+            }
+
+            if let Statement::BranchStatement(_) = else_branch.as_ref() {
+                branch.push_str(&format!("else {}", else_branch.accept(self)?));
+            } else {
+                branch.push_str(&format!("else{}", self.format_body(else_branch)?));
+            }
+        }
+
+        Ok(branch)
+    }
+
+    fn visit_while_statement(
+        &mut self,
+        while_statement: &super::stmt::WhileStatement,
+    ) -> Result<String, LoxError> {
+        if while_statement.increment.is_some() {
+            return self.format_for(None, while_statement);
+        }
+
+        Ok(format!(
+            "while ({}){}",
+            self.format_expression(&while_statement.condition)?,
+            self.format_body(&while_statement.body)?
+        ))
+    }
+
+    fn visit_continue_statement(
+        &mut self,
+        _continue_statement: &super::stmt::ContinueStatement,
+    ) -> Result<String, LoxError> {
+        Ok("continue;".into())
+    }
+
+    fn visit_break_statement(
+        &mut self,
+        _break_statement: &super::stmt::BreakStatement,
+    ) -> Result<String, LoxError> {
+        Ok("break;".into())
+    }
+
+    fn visit_function_statement(
+        &mut self,
+        function_statement: &super::stmt::FunctionStatement,
+    ) -> Result<String, LoxError> {
+        // Methods are written without `func`, and static ones are marked.
+        let keyword = match function_statement.function_type {
+            FuncType::Method | FuncType::Initializer => "",
+            FuncType::StaticMethod => "#[static] ",
+            _ => "func ",
+        };
+
+        Ok(format!(
+            "{}{}{}",
+            keyword,
+            function_statement.name.lexeme,
+            self.format_function(&function_statement.params, &function_statement.body)?
+        ))
+    }
+
+    fn visit_return_statement(
+        &mut self,
+        return_statement: &super::stmt::ReturnStatement,
+    ) -> Result<String, LoxError> {
+        match &return_statement.value {
+            Some(value) => Ok(format!("return {};", self.format_expression(value)?)),
+            None => Ok("return;".into()),
+        }
+    }
+
+    fn visit_class_statement(
+        &mut self,
+        class_statement: &super::stmt::ClassStatement,
+    ) -> Result<String, LoxError> {
+        // Static members are kept apart from the methods, so the members are
+        // put back into source order, comments included.
+        let mut members = class_statement.static_methods.clone();
+        members.extend(class_statement.static_fields.iter().cloned());
+        members.extend(class_statement.methods.iter().cloned());
+        members.sort_by_key(|member| match member {
+            Statement::FunctionStatement(method) => method.name.position,
+            Statement::CommentStatement(comment) => comment.token.position,
+            Statement::MultiVarStatement(field) => match field.vars.first() {
+                Some(Statement::VarStatement(var)) => var.name.position,
+                _ => (0, 0),
+            },
+            _ => (0, 0),
+        });
+
+        let superclass = match &class_statement.superclass {
+            Some(superclass) => format!(" extend {}", self.format_expression(superclass)?),
+            None => String::new(),
+        };
+
+        Ok(format!(
+            "class {}{} {}",
+            class_statement.name.lexeme,
+            superclass,
+            self.format_class_body(&members)?
+        ))
+    }
+
+    fn visit_comment_statement(
+        &mut self,
+        comment_statement: &super::stmt::CommentStatement,
+    ) -> Result<String, LoxError> {
+        Ok(comment_statement.token.lexeme.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rlox::lox::Lox;
+
+    fn format(source: &str) -> String {
+        Lox::format_source(source.into()).unwrap()
+    }
+
+    const MESSY: &str = "
+// counts up
+let   a=1,b ; // two names
+func add(x,y){return x+y;}
+class A extend B {   // about A
+  #[static] let  ORIGIN=0 ;
+  #[static] make(){ return A(); }
+    get( ) { return self.x ; }
+}
+while(a<10) when(a!=3){a=a+1;print a;}
+for(let i=0;i<3;i=i+1)print i ;
+if(a) print 1; else { print 2; }
+";
+
+    const CANONICAL: &str = "// counts up
+let a = 1, b; // two names
+func add(x, y) {
+    return x + y;
+}
+class A extend B { // about A
+    #[static] let ORIGIN = 0;
+    #[static] make() {
+        return A();
+    }
+    get() {
+        return self.x;
+    }
+}
+while (a < 10) when (a != 3) {
+    a = a + 1;
+    print a;
+}
+for (let i = 0; i < 3; i = i + 1)
+    print i;
+if (a)
+    print 1;
+else {
+    print 2;
+}
+";
+
+    #[test]
+    fn messy_source_is_formatted_canonically() {
+        assert_eq!(format(MESSY), CANONICAL);
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        let once = format(MESSY);
+        assert_eq!(format(&once), once);
+    }
+
+    #[test]
+    fn comments_are_kept() {
+        let source = "// keep me\nlet a = 1; // trailing\n";
+        assert_eq!(format(source), source);
+    }
+}
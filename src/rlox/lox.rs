@@ -1,3 +1,4 @@
+use std::cell::RefCell;
 use std::fs::read_to_string;
 
 use std::path::PathBuf;
@@ -6,10 +7,12 @@ use std::time::SystemTime;
 use crate::rlox::bytecode_interpreter::vm::VirtualMachine;
 
 use super::bytecode_interpreter::convertor::Convertor;
+use super::formatter::Formatter;
 use super::parser::Parser;
 use super::repl;
 use super::resolver::Resolver;
 use super::scanner::Scanner;
+use super::stmt::Statement;
 use super::token::Token;
 use super::types::TokenType;
 
@@ -29,24 +32,56 @@ pub fn had_error() {
     unsafe { HAD_ERROR = true }
 }
 
+thread_local! {
+    static SOURCE_LINES: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+}
+
+/// Keeps the lines of the source being run so that errors can quote the
+/// offending line.
+pub fn set_source(source: &str) {
+    SOURCE_LINES.with(|lines| *lines.borrow_mut() = source.split('\n').map(String::from).collect());
+}
+
 pub struct Lox;
 
 impl Lox {
     pub fn run_file(path: PathBuf) -> Result<(), LoxError> {
-        std::env::set_var("RLOX_RUN_MODE", "F");
-
         let string = read_to_string(path)?;
+        Self::run_source(string)
+    }
+
+    /// Runs source passed on the command line.
+    pub fn run_eval(source: String) -> Result<(), LoxError> {
+        Self::run_source(Self::eval_source(source))
+    }
+
+    /// A bare expression without a trailing `;` is turned into a statement
+    /// printing its value, other source is left as it is.
+    fn eval_source(source: String) -> String {
+        let trimmed = source.trim_end();
+        if trimmed.ends_with(';') || trimmed.ends_with('}') {
+            source
+        } else {
+            format!("print ({});", trimmed)
+        }
+    }
 
+    pub fn run_source(string: String) -> Result<(), LoxError> {
+        std::env::set_var("RLOX_RUN_MODE", "F");
+
+        set_source(&string);
         let mut scanner = Scanner::new(string);
 
+        let mut vm = VirtualMachine::new();
+
         if let Err(err) = scanner.scan_tokens() {
             Self::error(err);
             had_error();
+        } else {
+            Self::run(&mut vm, scanner.tokens);
         }
 
-        let mut vm = VirtualMachine::new();
-
-        Self::run(&mut vm, scanner.tokens);
+        vm.shutdown();
 
         if is_error() {
             eprintln!("Exit because error before!");
@@ -55,6 +90,68 @@ impl Lox {
         Ok(())
     }
 
+    pub fn format_file(path: PathBuf) -> Result<(), LoxError> {
+        let string = read_to_string(path)?;
+
+        set_source(&string);
+        match Self::format_source(string) {
+            Ok(source) => print!("{}", source),
+            Err(errors) => {
+                for e in errors {
+                    Self::error(e)
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Formats source the way `--format` prints it.
+    pub fn format_source(source: String) -> Result<String, Vec<LoxError>> {
+        let mut scanner = Scanner::with_comments(source);
+        scanner.scan_tokens().map_err(|e| vec![e])?;
+
+        let statements = Parser::new(scanner.tokens).parse()?;
+        Formatter::default()
+            .format(&statements)
+            .map_err(|e| vec![e])
+    }
+
+    /// Compiles a script without running it and prints the source map of
+    /// every function in it.
+    pub fn source_map_file(path: PathBuf) -> Result<(), LoxError> {
+        std::env::set_var("RLOX_RUN_MODE", "S");
+
+        let string = read_to_string(path)?;
+
+        set_source(&string);
+        let mut scanner = Scanner::new(string);
+        if let Err(err) = scanner.scan_tokens() {
+            Self::error(err);
+            return Ok(());
+        }
+
+        let errors = match Parser::new(scanner.tokens).parse() {
+            Ok(statements) => match Resolver::new().resolve(&statements) {
+                Ok(_) => match Convertor::default().convert(&statements) {
+                    Ok(function) => {
+                        print!("{}", function.source_map());
+                        vec![]
+                    }
+                    Err(err) => vec![err],
+                },
+                Err(err) => err,
+            },
+            Err(err) => err,
+        };
+
+        for e in errors {
+            Self::error(e)
+        }
+
+        Ok(())
+    }
+
     pub fn run_prompt() -> Result<(), LoxError> {
         std::env::set_var("RLOX_RUN_MODE", "R");
         let mut repl = repl::Repl::new();
@@ -72,16 +169,32 @@ impl Lox {
         match parser.parse() {
             Ok(statements) => match resolver.resolve(&statements) {
                 Ok(_) => {
+                    let is_repl = std::env::var("RLOX_RUN_MODE").unwrap() == "R";
+                    let has_value =
+                        matches!(statements.last(), Some(Statement::ExpressionStatement(_)));
+
                     let mut convertor = Convertor::default();
+                    if is_repl {
+                        convertor.keep_last_value();
+                    }
+
                     match convertor.convert(&statements) {
                         Ok(func) => match vm.interpret(func) {
-                            Ok(value) => value,
+                            Ok(value) => {
+                                if is_repl && has_value {
+                                    vm.record_result(value);
+                                }
+                            }
                             Err(err) => Self::error(err),
                         },
                         Err(err) => Self::error(err),
                     };
                 }
-                Err(err) => Self::error(err),
+                Err(err) => {
+                    for e in err {
+                        Self::error(e)
+                    }
+                }
             },
             Err(err) => {
                 for e in err {
@@ -102,6 +215,7 @@ impl Lox {
         match error {
             LoxError::ParseError {
                 position: line,
+                end,
                 lexeme,
                 msg,
                 token_type,
@@ -109,14 +223,19 @@ impl Lox {
                 if token_type == TokenType::Eof {
                     Self::report(line, "at end", msg.as_str())
                 } else {
-                    Self::report(line, format!("at `{}`", lexeme).as_str(), msg.as_str())
+                    Self::report(line, format!("at `{}`", lexeme).as_str(), msg.as_str());
+                    Self::underline(line, end);
                 }
             }
             LoxError::RuntimeError {
                 position,
+                end,
                 lexeme,
                 msg,
-            } => Self::report(position, format!("at `{}`", lexeme).as_str(), msg.as_str()),
+            } => {
+                Self::report(position, format!("at `{}`", lexeme).as_str(), msg.as_str());
+                Self::underline(position, end);
+            }
             LoxError::IoError { msg } => Self::report((0, 0), "", msg.as_str()),
             LoxError::ParseTokenError {
                 position: line,
@@ -145,4 +264,160 @@ impl Lox {
         println!("\x1b[1;31m{err_msg}\x1b[0m");
         had_error()
     }
+
+    /// Quotes the source line of `start` and marks the span up to `end`, or
+    /// up to the end of the line when the span continues on later lines.
+    fn underline(start: (usize, usize), end: (usize, usize)) {
+        SOURCE_LINES.with(|lines| {
+            let lines = lines.borrow();
+            let Some(line) = start.0.checked_sub(1).and_then(|i| lines.get(i)) else {
+                return;
+            };
+
+            println!(
+                "\x1b[1;31m    {line}\n    {}\x1b[0m",
+                Self::carets(line, start, end)
+            );
+        })
+    }
+
+    /// The `^` marks under the span of `line` from `start` to `end`.
+    fn carets(line: &str, start: (usize, usize), end: (usize, usize)) -> String {
+        // columns count chars, as the scanner does
+        let chars = line.chars().collect::<Vec<_>>();
+        let end = if end.0 == start.0 { end.1 } else { chars.len() };
+        let indent = chars
+            .iter()
+            .take(start.1)
+            .map(|c| if *c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        let width = chars.get(start.1..end).map_or(1, |span| span.len().max(1));
+
+        format!("{indent}{}", "^".repeat(width))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::run;
+    use super::Lox;
+    use crate::rlox::scanner::Scanner;
+
+    #[test]
+    fn eval_runs_statements_and_prints_bare_expressions() {
+        let statements = Lox::eval_source("print 1+2;".into());
+        assert_eq!(run(&statements), Ok("3\n".into()));
+        let expression = Lox::eval_source("1+2".into());
+        assert_eq!(expression, "print (1+2);");
+        assert_eq!(run(&expression), Ok("3\n".into()));
+    }
+
+    #[test]
+    fn identifier_span_is_underlined_whole() {
+        let line = "let total = count + 1;";
+        let mut scanner = Scanner::new(line.into());
+        scanner.scan_tokens().unwrap();
+
+        let count = &scanner.tokens[3];
+        assert_eq!(*count.lexeme, "count");
+        assert_eq!((count.position, count.end), ((1, 12), (1, 17)));
+        assert_eq!(
+            Lox::carets(line, count.position, count.end),
+            "            ^^^^^"
+        );
+    }
+
+    #[test]
+    fn empty_and_blank_files_run_cleanly() {
+        assert_eq!(run(""), Ok("".into()));
+        assert_eq!(run(" \n\t\n  "), Ok("".into()));
+    }
+}
+
+/// Helpers for tests that run source through the whole pipeline.
+#[cfg(test)]
+pub mod testing {
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    use crate::rlox::bytecode_interpreter::convertor::Convertor;
+    use crate::rlox::bytecode_interpreter::vm::VirtualMachine;
+    use crate::rlox::error::LoxError;
+    use crate::rlox::parser::Parser;
+    use crate::rlox::resolver::Resolver;
+    use crate::rlox::scanner::Scanner;
+    use crate::rlox::types::Function;
+
+    /// Collects what a test VM prints.
+    #[derive(Clone, Default)]
+    pub struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Output {
+        /// Takes what has been printed so far.
+        pub fn take(&self) -> String {
+            String::from_utf8(self.0.take()).unwrap()
+        }
+    }
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// The message of an error, without its position.
+    pub fn message(error: &LoxError) -> String {
+        match error {
+            LoxError::IoError { msg } => msg.clone(),
+            LoxError::ParseTokenError { msg, .. } => msg.to_string(),
+            LoxError::ParseError { msg, .. } | LoxError::RuntimeError { msg, .. } => msg.clone(),
+            LoxError::UnexpectedError { message } => message.clone(),
+        }
+    }
+
+    /// Compiles a script.
+    pub fn compile(source: &str) -> Result<Function, Vec<LoxError>> {
+        convert(source, Convertor::default())
+    }
+
+    /// Compiles a REPL line, which returns the value of its trailing
+    /// expression.
+    pub fn compile_line(source: &str) -> Result<Function, Vec<LoxError>> {
+        let mut convertor = Convertor::default();
+        convertor.keep_last_value();
+        convert(source, convertor)
+    }
+
+    fn convert(source: &str, convertor: Convertor) -> Result<Function, Vec<LoxError>> {
+        std::env::set_var("RLOX_RUN_MODE", "F");
+
+        let mut scanner = Scanner::new(source.to_string());
+        scanner.scan_tokens().map_err(|e| vec![e])?;
+        let statements = Parser::new(scanner.tokens).parse()?;
+        Resolver::new().resolve(&statements)?;
+
+        convertor.convert(&statements).map_err(|e| vec![e])
+    }
+
+    /// A VM that prints into the returned `Output`.
+    pub fn vm() -> (VirtualMachine, Output) {
+        std::env::set_var("RLOX_RUN_MODE", "F");
+        let out = Output::default();
+        (VirtualMachine::with_output(out.clone()), out)
+    }
+
+    /// Runs a script and returns what it printed, or the message of the
+    /// first error.
+    pub fn run(source: &str) -> Result<String, String> {
+        let (mut vm, out) = vm();
+        let function = compile(source).map_err(|e| message(&e[0]))?;
+        vm.interpret(function).map_err(|e| message(&e))?;
+        Ok(out.take())
+    }
 }
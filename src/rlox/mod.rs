@@ -1,6 +1,7 @@
 mod bytecode_interpreter;
 mod error;
 mod expr;
+mod formatter;
 pub mod lox;
 mod parser;
 mod repl;
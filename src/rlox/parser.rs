@@ -1,3 +1,4 @@
+use std::collections::VecDeque;
 use std::vec;
 
 use super::error::LoxError::ParseError;
@@ -11,11 +12,30 @@ use super::types::{FuncType, Literal};
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    /// Where the statement being parsed at the top of a function body
+    /// starts, as only such a statement can be an implicit return.
+    body_statement_start: Option<usize>,
+    /// Whether that function body is an `__init__`, which can't return a
+    /// value, so its trailing expression is only evaluated.
+    in_initializer: bool,
+    /// Comments left by a comment-keeping scanner, which are set aside and
+    /// turned into statements between the statements they sit between.
+    comments: VecDeque<Token>,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        let (comments, tokens): (Vec<_>, _) = tokens
+            .into_iter()
+            .partition(|token| token.token_type == TokenType::Comment);
+
+        Self {
+            tokens,
+            current: 0,
+            body_statement_start: None,
+            in_initializer: false,
+            comments: comments.into(),
+        }
     }
 
     pub fn parse(&mut self) -> Result<Vec<Statement>, Vec<LoxError>> {
@@ -23,11 +43,13 @@ impl Parser {
         let mut errors = vec![];
 
         while !self.is_at_end() {
+            self.comments_before_next(&mut statements);
             match self.declaration() {
                 Ok(stmt) => statements.push(stmt),
                 Err(e) => errors.push(e),
             }
         }
+        self.comments_before_next(&mut statements);
 
         if !errors.is_empty() {
             return Err(errors);
@@ -36,10 +58,30 @@ impl Parser {
         Ok(statements)
     }
 
+    // Precedence, from lowest to highest:
+    // assignment, ternary (`? :`, right-associative), `or`, `and`, equality,
+    // comparison, term, factor, unary, call, primary.
+    // So `a and b ? c : d` is `(a and b) ? c : d`, and `a ? b : c or d` is
+    // `a ? b : (c or d)`.
     fn expression(&mut self) -> Result<Expression> {
         self.assignment()
     }
 
+    /// Moves the comments that come before the next token into `statements`.
+    /// A comment on the line where the previous statement ends trails it.
+    /// Comments inside a statement end up after it.
+    fn comments_before_next(&mut self, statements: &mut Vec<Statement>) {
+        while self
+            .comments
+            .front()
+            .is_some_and(|comment| comment.position < self.peek().position || self.is_at_end())
+        {
+            let comment = self.comments.pop_front().unwrap();
+            let trailing = self.current > 0 && self.previous().position.0 == comment.position.0;
+            statements.push(Statement::create_comment_statement(comment, trailing));
+        }
+    }
+
     fn declaration(&mut self) -> Result<Statement> {
         if self.match_one(TokenType::Let) {
             return match self.var_declaration() {
@@ -51,11 +93,12 @@ impl Parser {
             };
         }
         if self.match_one(TokenType::Func) {
-            let function_type = if self.check(TokenType::Identifier) {
-                FuncType::Normal
-            } else {
-                FuncType::Lambda
-            };
+            let function_type =
+                if self.check(TokenType::Identifier) || self.peek().token_type.is_keyword() {
+                    FuncType::Normal
+                } else {
+                    FuncType::Lambda
+                };
 
             return match self.function(function_type) {
                 Ok(stmt) => Ok(stmt),
@@ -85,7 +128,13 @@ impl Parser {
     }
 
     fn class(&mut self) -> Result<Statement> {
-        let class_name = self.consume(TokenType::Identifier, "Expect a class name")?;
+        let class_name = self.consume_identifier("class name", "Expect a class name")?;
+        let superclass = if self.match_one(TokenType::Extend) {
+            let name = self.consume_identifier("superclass name", "Expect a superclass name")?;
+            Some(Expression::create_variable_expression(name))
+        } else {
+            None
+        };
         self.consume(
             TokenType::LeftBrace,
             format!("Expect `{{` after `{}`", class_name.lexeme).as_str(),
@@ -93,15 +142,25 @@ impl Parser {
 
         let mut methods = vec![];
         let mut static_methods = vec![];
+        let mut static_fields = vec![];
 
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            if self.check(TokenType::Static) {
-                self.advance();
-                static_methods.push(self.function(FuncType::StaticMethod)?)
+            self.comments_before_next(&mut methods);
+            if self.check(TokenType::RightBrace) {
+                break;
+            }
+
+            if self.match_one(TokenType::Static) {
+                if self.match_one(TokenType::Let) {
+                    static_fields.push(self.var_declaration()?)
+                } else {
+                    static_methods.push(self.function(FuncType::StaticMethod)?)
+                }
             } else {
                 methods.push(self.function(FuncType::Method)?)
             }
         }
+        self.comments_before_next(&mut methods);
 
         self.consume(
             TokenType::RightBrace,
@@ -110,12 +169,17 @@ impl Parser {
 
         Ok(Statement::create_class_statement(
             class_name,
+            superclass,
             methods,
             static_methods,
+            static_fields,
         ))
     }
 
-    fn function_params_and_body(&mut self) -> Result<(Vec<Token>, Vec<Statement>)> {
+    fn function_params_and_body(
+        &mut self,
+        is_initializer: bool,
+    ) -> Result<(Vec<Token>, Vec<Statement>)> {
         let params = {
             let mut params = vec![];
 
@@ -127,7 +191,7 @@ impl Parser {
                     ));
                 }
 
-                params.push(self.consume(TokenType::Identifier, "Expect parameter name.")?);
+                params.push(self.consume_identifier("parameter name", "Expect parameter name.")?);
                 if self.check(TokenType::Comma) {
                     self.advance();
                 }
@@ -139,16 +203,39 @@ impl Parser {
         self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
         self.consume(TokenType::LeftBrace, "Expect '{' before function body.")?;
 
-        let body = self.block_statement()?;
+        let body = self.function_body(is_initializer)?;
 
         Ok((params, body))
     }
 
+    /// Parses a function body like a block, except that an expression left
+    /// without a `;` at the end of it is returned from the function. An
+    /// `__init__` only evaluates it, as it can't return a value.
+    fn function_body(&mut self, is_initializer: bool) -> Result<Vec<Statement>> {
+        let mut statements = vec![];
+        // a body can be nested in a statement of another one, which goes on
+        // once this one ends
+        let enclosing = (self.body_statement_start, self.in_initializer);
+        self.in_initializer = is_initializer;
+
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            self.comments_before_next(&mut statements);
+            self.body_statement_start = Some(self.current);
+            statements.push(self.declaration()?)
+        }
+        self.comments_before_next(&mut statements);
+        (self.body_statement_start, self.in_initializer) = enclosing;
+
+        self.consume(TokenType::RightBrace, "Expect '}' after block")?;
+
+        Ok(statements)
+    }
+
     fn function(&mut self, kind: FuncType) -> Result<Statement> {
         match kind {
             FuncType::Normal | FuncType::Method | FuncType::StaticMethod => {
-                let name = self.consume(
-                    TokenType::Identifier,
+                let name = self.consume_identifier(
+                    "function name",
                     format!("Expect {} name.", kind).as_str(),
                 )?;
 
@@ -156,18 +243,20 @@ impl Parser {
                     TokenType::LeftParen,
                     format!("Expect '(' after {} name.", kind).as_str(),
                 )?;
-                let (params, body) = self.function_params_and_body()?;
+                let is_initializer = kind == FuncType::Method && *name.lexeme == "__init__";
+                let (params, body) = self.function_params_and_body(is_initializer)?;
 
                 Ok(Statement::create_function_statement(
                     name, params, body, kind,
                 ))
             }
             FuncType::Lambda => {
+                let keyword = self.previous();
                 self.consume(TokenType::LeftParen, "Expect '(' after lambda.")?;
 
-                let (params, body) = self.function_params_and_body()?;
+                let (params, body) = self.function_params_and_body(false)?;
 
-                let lambda = Expression::create_lambda_expression(params, body);
+                let lambda = Expression::create_lambda_expression(keyword, params, body);
 
                 Ok(Statement::create_expression_statement(
                     lambda,
@@ -185,18 +274,18 @@ impl Parser {
         let mut vars = vec![];
 
         while !self.is_at_end() && !self.check(TokenType::Semicolon) {
-            let name = self.consume(TokenType::Identifier, "Expect a variable name.")?;
+            let name = self.consume_identifier("variable name", "Expect a variable name.")?;
             let mut initializer = None;
             if self.match_one(TokenType::Equal) {
                 initializer = Some(self.ternary()?)
             }
             vars.push(Statement::create_var_statement(name, initializer));
-            if !self.check(TokenType::Semicolon) {
-                self.consume(TokenType::Comma, "Expect ',' after value")?;
+            if !self.match_one(TokenType::Comma) {
+                break;
             }
         }
 
-        self.consume(TokenType::Semicolon, "Expect ';' after value")?;
+        self.consume_semicolon("Expect ';' after value")?;
 
         Ok(Statement::create_multi_var_statement(vars))
     }
@@ -214,6 +303,10 @@ impl Parser {
             return self.print_statement(self.previous());
         }
 
+        if self.match_one(TokenType::Assert) {
+            return self.assert_statement(self.previous());
+        }
+
         if self.match_one(TokenType::Return) {
             return self.return_statement();
         }
@@ -224,13 +317,13 @@ impl Parser {
 
         if self.match_one(TokenType::Break) {
             let token = self.previous();
-            self.consume(TokenType::Semicolon, "Expect ';' after 'break'")?;
+            self.consume_semicolon("Expect ';' after 'break'")?;
             return Ok(Statement::create_break_statement(token));
         }
 
         if self.match_one(TokenType::Continue) {
             let token = self.previous();
-            self.consume(TokenType::Semicolon, "Expect ';' after 'continue'")?;
+            self.consume_semicolon("Expect ';' after 'continue'")?;
             return Ok(Statement::create_continue_statement(token));
         }
 
@@ -249,7 +342,7 @@ impl Parser {
             Some(self.expression()?)
         };
 
-        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+        self.consume_semicolon("Expect ';' after return value.")?;
 
         Ok(Statement::create_return_statement(key_word, value))
     }
@@ -275,16 +368,29 @@ impl Parser {
 
         let semicolon = self.consume(TokenType::Semicolon, "Expect ';' after loop condition")?;
 
+        // The increment may update several loop variables, like
+        // `i = i + 1, j = j - 1`, each in an expression statement of its own.
         let increment = if self.check(TokenType::RightParen) {
             self.consume(TokenType::RightParen, "Expect ')' after 'for'")?;
             None
         } else {
-            let expr = self.expression()?;
+            let mut exprs = vec![self.expression()?];
+            while self.match_one(TokenType::Comma) {
+                exprs.push(self.expression()?);
+            }
             let right_paren = self.consume(TokenType::RightParen, "Expect ')' after 'for'")?;
-            Some(Statement::create_expression_statement(expr, right_paren))
+            let mut statements = exprs
+                .into_iter()
+                .map(|expr| Statement::create_expression_statement(expr, right_paren.clone()))
+                .collect::<Vec<_>>();
+            if statements.len() == 1 {
+                statements.pop()
+            } else {
+                Some(Statement::create_block_statement(statements))
+            }
         };
 
-        let mut body = self.statement()?;
+        let mut body = self.loop_body()?;
 
         let mut incr = None;
 
@@ -313,7 +419,7 @@ impl Parser {
         self.consume(TokenType::LeftParen, "Expect '(' after 'if'")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expect ')' after the condition")?;
-        let body = self.statement()?;
+        let body = self.loop_body()?;
 
         Ok(Statement::create_while_statement(
             condition,
@@ -322,7 +428,30 @@ impl Parser {
         ))
     }
 
+    /// Parses a loop body, optionally guarded by `when (guard)`. The guard is
+    /// sugar for wrapping the body in an `if`, so an iteration whose guard is
+    /// false skips the body like a `continue` would.
+    fn loop_body(&mut self) -> Result<Statement> {
+        if !self.match_one(TokenType::When) {
+            return self.statement();
+        }
+
+        let keyword = self.previous();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'when'")?;
+        let guard = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after the guard")?;
+        let body = self.statement()?;
+
+        Ok(Statement::create_branch_statement(
+            keyword,
+            guard,
+            Box::new(body),
+            None,
+        ))
+    }
+
     fn branch_statement(&mut self) -> Result<Statement> {
+        let keyword = self.previous();
         self.consume(TokenType::LeftParen, "Expect '(' after 'if'")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expect ')' after the condition")?;
@@ -335,6 +464,7 @@ impl Parser {
         }
 
         Ok(Statement::create_branch_statement(
+            keyword,
             condition,
             Box::new(then_branch),
             else_branch,
@@ -345,38 +475,66 @@ impl Parser {
         let mut statements = vec![];
 
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            self.comments_before_next(&mut statements);
             statements.push(self.declaration()?)
         }
+        self.comments_before_next(&mut statements);
 
         self.consume(TokenType::RightBrace, "Expect '}' after block")?;
 
         Ok(statements)
     }
 
+    /// `print a, b;` prints its values on one line, separated by spaces.
     fn print_statement(&mut self, keyword: Token) -> Result<Statement> {
-        let value = self.expression()?;
+        let mut values = vec![self.expression()?];
+        while self.match_one(TokenType::Comma) {
+            values.push(self.expression()?);
+        }
 
-        self.consume(
-            TokenType::Semicolon,
-            format!("Expect ';' after {}", value).as_str(),
-        )?;
+        self.consume_semicolon(format!("Expect ';' after {}", values.last().unwrap()).as_str())?;
+
+        Ok(Statement::create_print_statement(values, keyword))
+    }
+
+    fn assert_statement(&mut self, keyword: Token) -> Result<Statement> {
+        let condition = self.expression()?;
+        self.consume_semicolon(format!("Expect ';' after {}", condition).as_str())?;
 
-        Ok(Statement::create_print_statement(value, keyword))
+        Ok(Statement::create_assert_statement(condition, keyword))
     }
 
     fn expression_statement(&mut self) -> Result<Statement> {
+        let start = self.current;
         let expr = self.expression()?;
 
-        let semicolon = self.consume(
-            TokenType::Semicolon,
-            format!("Expect ';' after {}", expr).as_str(),
-        )?;
+        if self.body_statement_start == Some(start) && self.check(TokenType::RightBrace) {
+            if self.in_initializer {
+                return Ok(Statement::create_expression_statement(
+                    expr,
+                    self.previous(),
+                ));
+            }
+            let key_word = self.tokens[start].clone();
+            return Ok(Statement::create_return_statement(key_word, Some(expr)));
+        }
+
+        let semicolon = self.consume_semicolon(format!("Expect ';' after {}", expr).as_str())?;
 
         Ok(Statement::create_expression_statement(expr, semicolon))
     }
 
     fn assignment(&mut self) -> Result<Expression> {
-        let expr = self.or()?;
+        // `(a, b` can't start any other expression, as there is no comma
+        // operator.
+        if self.check(TokenType::LeftParen)
+            && self.check_nth(1, TokenType::Identifier)
+            && self.check_nth(2, TokenType::Comma)
+        {
+            return self.multi_assignment();
+        }
+
+        let expr = self.ternary()?;
 
         if self.match_many(vec![
             TokenType::Equal,
@@ -410,11 +568,32 @@ impl Parser {
                     }
                 }
             } else if let Expression::GetExpression(g) = expr {
+                // There is no compound form of a property set, and turning
+                // `a.b += 1` into `a.b = 1` would silently drop the operator.
+                if op.token_type != TokenType::Equal {
+                    return Err(LoxError::create_runtime_error(
+                        &op,
+                        "Invalid compound assignment target".into(),
+                    ));
+                }
                 return Ok(Expression::create_set_expression(
                     g.object,
                     g.name,
                     Box::new(value),
                 ));
+            } else if let Expression::IndexExpression(i) = expr {
+                if op.token_type != TokenType::Equal {
+                    return Err(LoxError::create_runtime_error(
+                        &op,
+                        "Invalid compound assignment target".into(),
+                    ));
+                }
+                return Ok(Expression::create_index_set_expression(
+                    i.object,
+                    i.bracket,
+                    i.index,
+                    Box::new(value),
+                ));
             }
 
             return Err(LoxError::create_runtime_error(
@@ -426,6 +605,54 @@ impl Parser {
         Ok(expr)
     }
 
+    /// Parses `(a, b) = (x, y)`. All of the values are evaluated before any
+    /// target is assigned, so `(a, b) = (b, a)` swaps `a` and `b`.
+    fn multi_assignment(&mut self) -> Result<Expression> {
+        self.consume(
+            TokenType::LeftParen,
+            "Expect '(' before assignment targets.",
+        )?;
+        let mut names = vec![];
+        while !self.check(TokenType::RightParen) {
+            names.push(self.consume_identifier("variable name", "Expect variable name.")?);
+            if !self.match_one(TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(
+            TokenType::RightParen,
+            "Expect ')' after assignment targets.",
+        )?;
+
+        let equal = self.consume(TokenType::Equal, "Expect '=' after assignment targets.")?;
+
+        self.consume(TokenType::LeftParen, "Expect '(' before assigned values.")?;
+        let mut values = vec![];
+        while !self.check(TokenType::RightParen) {
+            values.push(self.expression()?);
+            if !self.match_one(TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after assigned values.")?;
+
+        if names.len() != values.len() {
+            return Err(Self::error(
+                &equal,
+                format!(
+                    "Expect {} values to assign but got {}.",
+                    names.len(),
+                    values.len()
+                )
+                .as_str(),
+            ));
+        }
+
+        Ok(Expression::create_multi_assign_expression(
+            names, equal, values,
+        ))
+    }
+
     fn or(&mut self) -> Result<Expression> {
         let mut expr = self.and()?;
 
@@ -439,11 +666,11 @@ impl Parser {
     }
 
     fn and(&mut self) -> Result<Expression> {
-        let mut expr = self.ternary()?;
+        let mut expr = self.equality()?;
 
         while self.match_one(TokenType::And) && !self.is_at_end() {
             let op = self.previous();
-            let right = self.ternary()?;
+            let right = self.equality()?;
             expr = Expression::create_logical_expression(Box::new(expr), op, Box::new(right));
         }
 
@@ -451,7 +678,7 @@ impl Parser {
     }
 
     fn ternary(&mut self) -> Result<Expression> {
-        let cmp = self.equality();
+        let cmp = self.or();
 
         if self.match_one(TokenType::QuestionMark) {
             let true_value = self.ternary();
@@ -560,8 +787,14 @@ impl Parser {
                 callee = Expression::create_call_expression(Box::new(callee), paren, args);
             } else if self.match_one(TokenType::Dot) {
                 let name =
-                    self.consume(TokenType::Identifier, "Expect property name after '.'.")?;
+                    self.consume_identifier("property name", "Expect property name after '.'.")?;
                 callee = Expression::create_get_expression(Box::new(callee), name)
+            } else if self.match_one(TokenType::LeftBracket) {
+                let bracket = self.previous();
+                let index = self.expression()?;
+                self.consume(TokenType::RightBracket, "Expect ']' after index.")?;
+                callee =
+                    Expression::create_index_expression(Box::new(callee), bracket, Box::new(index))
             } else {
                 break;
             }
@@ -591,11 +824,12 @@ impl Parser {
     }
 
     fn lambda(&mut self) -> Result<Expression> {
+        let keyword = self.previous();
         self.consume(TokenType::LeftParen, "Expect '(' after func.")?;
 
-        let (params, body) = self.function_params_and_body()?;
+        let (params, body) = self.function_params_and_body(false)?;
 
-        let lambda = Expression::create_lambda_expression(params, body);
+        let lambda = Expression::create_lambda_expression(keyword, params, body);
 
         Ok(lambda)
     }
@@ -634,6 +868,41 @@ impl Parser {
             Ok(self.lambda()?)
         } else if self.match_one(TokenType::RSelf) {
             Ok(Expression::create_self_expression(self.previous()))
+        } else if self.match_one(TokenType::LeftBracket) {
+            let bracket = self.previous();
+            let mut elements = vec![];
+            while !self.check(TokenType::RightBracket) {
+                elements.push(self.expression()?);
+                if !self.match_one(TokenType::Comma) {
+                    break;
+                }
+            }
+            self.consume(TokenType::RightBracket, "Expect ']' after array elements.")?;
+            Ok(Expression::create_array_expression(bracket, elements))
+        } else if self.match_one(TokenType::LeftBrace) {
+            // A `{` that starts a statement is a block, so one only gets
+            // here where an expression is expected.
+            let brace = self.previous();
+            let mut entries = vec![];
+            while !self.check(TokenType::RightBrace) {
+                let key = self.expression()?;
+                self.consume(
+                    TokenType::Colon,
+                    format!("Expect ':' after map key {}.", key).as_str(),
+                )?;
+                entries.push((key, self.expression()?));
+                if !self.match_one(TokenType::Comma) {
+                    break;
+                }
+            }
+            self.consume(TokenType::RightBrace, "Expect '}' after map entries.")?;
+            Ok(Expression::create_map_expression(brace, entries))
+        } else if self.match_one(TokenType::Super) {
+            let keyword = self.previous();
+            self.consume(TokenType::Dot, "Expect '.' after `super`.")?;
+            let method =
+                self.consume_identifier("method name", "Expect superclass method name.")?;
+            Ok(Expression::create_super_expression(keyword, method))
         } else {
             use TokenType::{
                 BangEqual, Comma, EqualEqual, Greater, GreaterEqual, Less, LessEqual, Slash, Star,
@@ -682,6 +951,40 @@ impl Parser {
         Err(Self::error(self.peek(), msg))
     }
 
+    /// Like `consume`, but a missing `;` is reported right after the
+    /// statement it should end, not at the token that follows, which is
+    /// often on the next line.
+    fn consume_semicolon(&mut self, msg: &str) -> Result<Token> {
+        if self.check(TokenType::Semicolon) {
+            return Ok(self.advance());
+        }
+
+        let previous = self.previous();
+        Err(ParseError {
+            position: previous.end,
+            end: (previous.end.0, previous.end.1 + 1),
+            lexeme: previous.display_lexeme(),
+            token_type: previous.token_type,
+            msg: msg.into(),
+        })
+    }
+
+    fn consume_identifier(&mut self, usage: &str, msg: &str) -> Result<Token> {
+        if self.peek().token_type.is_keyword() {
+            let token = self.peek();
+            return Err(Self::error(
+                token,
+                format!(
+                    "`{}` is a reserved keyword and cannot be used as a {}.",
+                    token.lexeme, usage
+                )
+                .as_str(),
+            ));
+        }
+
+        self.consume(TokenType::Identifier, msg)
+    }
+
     fn check(&self, token_type: TokenType) -> bool {
         if self.is_at_end() {
             return false;
@@ -697,6 +1000,12 @@ impl Parser {
         self.previous()
     }
 
+    fn check_nth(&self, n: usize, token_type: TokenType) -> bool {
+        self.tokens
+            .get(self.current + n)
+            .is_some_and(|token| token.token_type == token_type)
+    }
+
     fn peek(&self) -> &Token {
         self.tokens.get(self.current).unwrap()
     }
@@ -708,7 +1017,8 @@ impl Parser {
     fn error(token: &Token, msg: &str) -> LoxError {
         ParseError {
             position: token.position,
-            lexeme: token.lexeme.clone(),
+            end: token.end,
+            lexeme: token.display_lexeme(),
             token_type: token.token_type,
             msg: msg.into(),
         }
@@ -738,3 +1048,142 @@ impl Parser {
         self.peek().token_type == TokenType::Eof
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Parser;
+    use crate::rlox::error::LoxError;
+    use crate::rlox::lox::testing::{message, run};
+    use crate::rlox::scanner::Scanner;
+    use crate::rlox::stmt::Statement;
+
+    fn parse(source: &str) -> Result<Vec<Statement>, Vec<LoxError>> {
+        let mut scanner = Scanner::new(source.to_string());
+        scanner.scan_tokens().unwrap();
+        Parser::new(scanner.tokens).parse()
+    }
+
+    #[test]
+    fn error_at_end_of_a_for_loop_names_the_end() {
+        let errors = parse("for (let i = 0; i < 3;").unwrap_err();
+        match &errors[0] {
+            LoxError::ParseError { lexeme, .. } => assert_eq!(**lexeme, "end"),
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn ternary_binds_looser_than_and_or() {
+        assert_eq!(run("print true and false ? 1 : 2;"), Ok("2\n".into()));
+        assert_eq!(run("print false or true ? 1 : 2;"), Ok("1\n".into()));
+        assert_eq!(run("print false ? 1 : true ? 2 : 3;"), Ok("2\n".into()));
+    }
+
+    #[test]
+    fn keyword_as_a_name_is_reported_as_reserved() {
+        let errors = parse("let while = 1;").unwrap_err();
+        assert_eq!(
+            message(&errors[0]),
+            "`while` is a reserved keyword and cannot be used as a variable name."
+        );
+        let errors = parse("func class() {}").unwrap_err();
+        assert_eq!(
+            message(&errors[0]),
+            "`class` is a reserved keyword and cannot be used as a function name."
+        );
+    }
+
+    #[test]
+    fn trailing_expression_is_returned_from_a_function() {
+        assert_eq!(run("func f(){ 1 + 2 } print f();"), Ok("3\n".into()));
+        assert_eq!(
+            run("func g(){ print 1; } print g();"),
+            Ok("1\nnil\n".into())
+        );
+        let nested = "func h(){ let k = func(){ 1 }; k() + 1 } print h();";
+        assert_eq!(run(nested), Ok("2\n".into()));
+    }
+
+    #[test]
+    fn trailing_expression_of_an_initializer_is_not_returned() {
+        let statements = parse("class A { __init__() { self.x = 1 } }").unwrap();
+        let Statement::ClassStatement(class) = &statements[0] else {
+            panic!("expected a class");
+        };
+        let Statement::FunctionStatement(init) = &class.methods[0] else {
+            panic!("expected a method");
+        };
+        assert!(matches!(init.body[0], Statement::ExpressionStatement(_)));
+    }
+
+    #[test]
+    fn loop_body_runs_only_when_the_guard_holds() {
+        let source = "for (let i = 0; i < 10; i = i + 1) when (i % 2 == 0) { print i; }";
+        assert_eq!(run(source), Ok("0\n2\n4\n6\n8\n".into()));
+        let source =
+            "let i = 0; let n = 0; while (i < 5) when ((i = i + 1) != 3) n = n + i; print n;";
+        assert_eq!(run(source), Ok("12\n".into()));
+    }
+
+    #[test]
+    fn when_is_a_reserved_keyword() {
+        let errors = parse("let when = 1;").unwrap_err();
+        assert_eq!(
+            message(&errors[0]),
+            "`when` is a reserved keyword and cannot be used as a variable name."
+        );
+    }
+
+    #[test]
+    fn multiple_assignment_swaps_values() {
+        let source = "let a = 1; let b = 2; (a, b) = (b, a); print a; print b;";
+        assert_eq!(run(source), Ok("2\n1\n".into()));
+        let local =
+            "{ let a = 1; let b = 2; let c = 3; (a, b, c) = (c, a, b); print a; print b; print c; }";
+        assert_eq!(run(local), Ok("3\n1\n2\n".into()));
+
+        let err = run("let a = 1; let b = 2; (a, b) = (1, 2, 3);").unwrap_err();
+        assert!(err.starts_with("Expect 2 values to assign but got 3."));
+    }
+
+    #[test]
+    fn print_takes_several_values() {
+        assert_eq!(run("print 1, \"a\", true;"), Ok("1 a true\n".into()));
+        assert_eq!(run("print \"a b\";"), Ok("a b\n".into()));
+    }
+
+    #[test]
+    fn for_loop_with_two_variables_counting_toward_each_other() {
+        let source = "for (let i = 0, j = 6; i < j; i = i + 1, j = j - 1) print i, j;";
+        assert_eq!(run(source), Ok("0 6\n1 5\n2 4\n".into()));
+    }
+
+    #[test]
+    fn missing_semicolon_points_right_after_the_statement() {
+        for (source, position) in [
+            ("let a = 1\nprint a;", (1, 9)),
+            ("print 12\nlet b;", (1, 8)),
+            ("func f() { return 1\n}", (1, 19)),
+            ("f(1)\nf(2);", (1, 4)),
+        ] {
+            match &parse(source).unwrap_err()[0] {
+                LoxError::ParseError {
+                    position: p, msg, ..
+                } => {
+                    assert_eq!(*p, position, "{source:?}");
+                    assert!(msg.starts_with("Expect ';'"), "{msg}");
+                }
+                err => panic!("unexpected error {:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn compound_assignment_needs_a_variable_target() {
+        let error = |source: &str| message(&parse(source).unwrap_err()[0]);
+        assert_eq!(error("a.x += 1;"), "Invalid compound assignment target");
+        assert_eq!(error("a[0] *= 2;"), "Invalid compound assignment target");
+        assert_eq!(error("(a + b) -= 1;"), "Invalid assignment target");
+        assert_eq!(run("let a = 1; a += 2; a *= 3; print a;"), Ok("9\n".into()));
+    }
+}
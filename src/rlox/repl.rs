@@ -4,6 +4,7 @@ use rustyline::{Cmd, Config, Editor, KeyEvent};
 use rustyline_derive::{Completer, Helper, Hinter, Validator};
 
 use std::borrow::Cow::{self, Borrowed};
+use std::time::Duration;
 
 use super::bytecode_interpreter::vm::VirtualMachine;
 use super::lox::{self, Lox};
@@ -33,6 +34,11 @@ impl Highlighter for MyHelper {
 
 pub struct Repl {
     editor: Editor<MyHelper>,
+    /// Prompt template from `RLOX_REPL_PROMPT`, where `{count}` stands for
+    /// the input number. `[{count:4}]: ` is used when it is unset.
+    prompt: Option<String>,
+    /// Whether `RLOX_REPL_BANNER` asks for a banner on startup.
+    banner: bool,
 }
 
 impl Repl {
@@ -50,33 +56,137 @@ impl Repl {
         editor.set_helper(Some(helper));
         editor.bind_sequence(KeyEvent::from('\t'), Cmd::Insert(1, "\t".into()));
 
-        Self { editor }
+        Self {
+            editor,
+            prompt: std::env::var("RLOX_REPL_PROMPT").ok(),
+            banner: std::env::var("RLOX_REPL_BANNER").is_ok(),
+        }
+    }
+
+    fn prompt(&self, count: usize) -> String {
+        match &self.prompt {
+            Some(prompt) => prompt.replace("{count}", &count.to_string()),
+            None => format!("[{count:4}]: "),
+        }
+    }
+
+    /// Handles a `:command` line. `:timeout N` aborts each following line
+    /// after N milliseconds and `:timeout off` lifts the limit again.
+    fn command(vm: &mut VirtualMachine, line: &str) {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words.as_slice() {
+            [":timeout", "off"] => vm.set_timeout(None),
+            [":timeout", ms] => match ms.parse::<u64>() {
+                Ok(ms) if ms > 0 => vm.set_timeout(Some(Duration::from_millis(ms))),
+                _ => println!("Usage: :timeout <milliseconds | off>"),
+            },
+            [command, ..] => println!("Unknown command `{command}`."),
+            [] => (),
+        }
     }
 
     pub fn run(&mut self, run_fn: fn(vm: &mut VirtualMachine, tokens: Vec<Token>) -> ()) {
         let mut count = 1;
         let mut vm = VirtualMachine::new();
 
+        if self.banner {
+            println!("rlox {}", env!("CARGO_PKG_VERSION"));
+            println!("Press Ctrl-D to exit.");
+        }
+
         loop {
-            let p = format!("[{count:4}]: ");
+            let p = self.prompt(count);
             self.editor.helper_mut().unwrap().colored_prompt = format!("\x1b[1;32m{p}\x1b[0m");
             let readline = self.editor.readline(&p);
 
             match readline {
+                Ok(line) if line.trim_start().starts_with(':') => Self::command(&mut vm, &line),
                 Ok(line) => {
-                    let mut scanner = Scanner::new(line);
-
-                    if let Err(err) = scanner.scan_tokens() {
-                        Lox::error(err);
-                        lox::had_error();
+                    if Self::line(&mut vm, line, run_fn) {
+                        count += 1;
                     }
-
-                    run_fn(&mut vm, scanner.tokens);
                 }
                 Err(_) => break,
             }
-            count += 1;
             lox::no_error();
         }
     }
+
+    /// Runs one line of input. Returns whether it counts as an input, as a
+    /// blank line is skipped without advancing the prompt.
+    fn line(
+        vm: &mut VirtualMachine,
+        line: String,
+        run_fn: fn(vm: &mut VirtualMachine, tokens: Vec<Token>) -> (),
+    ) -> bool {
+        if line.trim().is_empty() {
+            return false;
+        }
+
+        lox::set_source(&line);
+        let mut scanner = Scanner::new(line);
+
+        if let Err(err) = scanner.scan_tokens() {
+            Lox::error(err);
+            lox::had_error();
+        } else {
+            run_fn(vm, scanner.tokens);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Repl;
+    use crate::rlox::lox::testing::{compile_line, message, vm};
+
+    #[test]
+    fn blank_lines_are_skipped_without_advancing_the_prompt() {
+        let (mut vm, _) = vm();
+        assert!(!Repl::line(&mut vm, "".into(), |_, _| panic!(
+            "ran a blank line"
+        )));
+        assert!(!Repl::line(&mut vm, " \t ".into(), |_, _| panic!(
+            "ran a blank line"
+        )));
+        assert!(Repl::line(&mut vm, "1;".into(), |_, tokens| assert_eq!(
+            tokens.len(),
+            3
+        )));
+    }
+
+    #[test]
+    fn prompt_follows_its_template() {
+        let repl = Repl {
+            prompt: Some("lox {count}> ".into()),
+            ..Repl::new()
+        };
+        assert_eq!(repl.prompt(12), "lox 12> ");
+        let repl = Repl {
+            prompt: None,
+            ..Repl::new()
+        };
+        assert_eq!(repl.prompt(12), "[  12]: ");
+    }
+
+    #[test]
+    fn timeout_stops_a_line_and_the_next_one_still_runs() {
+        let (mut vm, out) = vm();
+        Repl::command(&mut vm, ":timeout 20");
+
+        let err = vm
+            .interpret(compile_line("while (true) {}").unwrap())
+            .unwrap_err();
+        assert_eq!(message(&err), "Execution timed out.");
+
+        vm.interpret(compile_line("print 1;").unwrap()).unwrap();
+        assert_eq!(out.take(), "1\n");
+
+        // lifting the limit again lets a long loop finish
+        Repl::command(&mut vm, ":timeout off");
+        let source = "let i = 0; while (i < 100000) { i = i + 1; } print i;";
+        vm.interpret(compile_line(source).unwrap()).unwrap();
+        assert_eq!(out.take(), "100000\n");
+    }
 }
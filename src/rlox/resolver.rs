@@ -1,9 +1,14 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 use super::{
+    bytecode_interpreter::native::natives,
     error::LoxError,
     expr::{Expression, Visitor as ExprVisitor},
     stmt::{FunctionStatement, Statement, Visitor as StmtVisitor},
+    token::Token,
     types::{ClassType, FuncType},
 };
 
@@ -12,7 +17,21 @@ pub struct Resolver {
     function_type: FuncType,
     class_type: ClassType,
     is_in_while: bool,
-    var_use_table: HashMap<Rc<String>, bool>,
+    /// Whether each variable is used, along with where it was declared.
+    var_use_table: HashMap<Rc<String>, (Token, bool)>,
+    function_depth: usize,
+    unassigned: HashMap<Rc<String>, usize>,
+    unassigned_reads: Vec<Token>,
+    is_strict: bool,
+    /// Every name declared anywhere in the program, natives included.
+    declared: HashSet<Rc<String>>,
+    function_names: HashSet<Rc<String>>,
+    reads: Vec<Token>,
+    assignments: Vec<Token>,
+    /// Functions that call themselves before they can possibly return.
+    recursive_functions: Vec<Token>,
+    /// Assignments whose value is overwritten before it is ever read.
+    dead_stores: Vec<Token>,
 }
 
 #[allow(unused)]
@@ -23,29 +42,130 @@ impl Resolver {
             class_type: ClassType::None,
             is_in_while: false,
             var_use_table: HashMap::new(),
+            function_depth: 0,
+            unassigned: HashMap::new(),
+            unassigned_reads: vec![],
+            is_strict: std::env::var("RLOX_STRICT").is_ok(),
+            declared: natives()
+                .into_iter()
+                .map(|native| Rc::new(native.name.to_string()))
+                .collect(),
+            function_names: HashSet::new(),
+            reads: vec![],
+            assignments: vec![],
+            recursive_functions: vec![],
+            dead_stores: vec![],
         }
     }
 
-    pub fn resolve(&mut self, statements: &[Statement]) -> Result<(), LoxError> {
-        self.resolve_statements(statements)?;
-        if std::env::var("RLOX_RUN_MODE").unwrap().eq("F") {
-            self.var_use_table
-                .iter()
-                .filter(|(_, &used)| !used)
-                .for_each(|(name, _)| {
-                    println!("\x1b[1;33m[WARN]:\x1b[0m Unused variable `{}`", name)
-                });
+    /// Resolves a whole program. When running a file, it then reports the
+    /// problems found along the way as warnings, or as errors in strict mode.
+    pub fn resolve(&mut self, statements: &[Statement]) -> Result<(), Vec<LoxError>> {
+        self.resolve_statements(statements).map_err(|e| vec![e])?;
+        if !std::env::var("RLOX_RUN_MODE").unwrap().eq("F") {
+            return Ok(());
+        }
+
+        let problems = self.problems();
+
+        if self.is_strict && !problems.is_empty() {
+            return Err(problems
+                .into_iter()
+                .map(|(token, msg)| LoxError::ParseError {
+                    position: token.position,
+                    end: token.end,
+                    lexeme: token.lexeme.clone(),
+                    token_type: token.token_type,
+                    msg,
+                })
+                .collect());
         }
+
+        problems.into_iter().for_each(|(token, msg)| {
+            println!(
+                "\x1b[1;33m[WARN]:\x1b[0m [{:2},{:2}] {}",
+                token.position.0, token.position.1, msg
+            )
+        });
         Ok(())
     }
 
-    fn variable_define(&mut self, name: Rc<String>) {
-        self.var_use_table.insert(name, false);
+    /// The problems found while resolving, in source order.
+    fn problems(&self) -> Vec<(&Token, String)> {
+        let mut problems = self
+            .var_use_table
+            .values()
+            .filter(|(_, used)| !used)
+            .map(|(token, _)| (token, format!("Unused variable `{}`", token.lexeme)))
+            .collect::<Vec<_>>();
+        problems.extend(self.unassigned_reads.iter().map(|token| {
+            let msg = format!("Variable `{}` is read before being assigned", token.lexeme);
+            (token, msg)
+        }));
+        problems.extend(
+            self.assignments
+                .iter()
+                .filter(|token| self.function_names.contains(&token.lexeme))
+                .map(|token| (token, format!("Assigning to function `{}`", token.lexeme))),
+        );
+        problems.extend(self.recursive_functions.iter().map(|token| {
+            let msg = format!(
+                "Function `{}` always calls itself, possible infinite recursion",
+                token.lexeme
+            );
+            (token, msg)
+        }));
+        problems.extend(self.dead_stores.iter().map(|token| {
+            let msg = format!("Value assigned to `{}` is never read", token.lexeme);
+            (token, msg)
+        }));
+        // Reading a name declared nowhere always fails at runtime, so it is
+        // only worth reporting when it stops the program from running.
+        if self.is_strict {
+            problems.extend(
+                self.reads
+                    .iter()
+                    .filter(|token| !self.declared.contains(&token.lexeme))
+                    .map(|token| (token, format!("Undefined variable `{}`.", token.lexeme))),
+            );
+        }
+        problems.sort_by_key(|(token, _)| token.position);
+        problems
+    }
+
+    fn variable_define(&mut self, token: &Token) {
+        self.declared.insert(token.lexeme.clone());
+        self.var_use_table
+            .insert(token.lexeme.clone(), (token.clone(), false));
+    }
+
+    fn params_define(&mut self, params: &[Token]) {
+        self.declared
+            .extend(params.iter().map(|param| param.lexeme.clone()));
+    }
+
+    // A variable declared without an initializer is tracked until its first
+    // assignment. Reads from a nested function are not reported, as the
+    // function may well be called after the assignment.
+    fn variable_declared_unassigned(&mut self, name: Rc<String>) {
+        self.unassigned.insert(name, self.function_depth);
+    }
+
+    fn variable_assigned(&mut self, name: &Rc<String>) {
+        self.unassigned.remove(name);
+    }
+
+    fn variable_read(&mut self, token: &Token) {
+        if self.unassigned.get(&token.lexeme) == Some(&self.function_depth) {
+            self.unassigned_reads.push(token.clone());
+        }
+        self.reads.push(token.clone());
+        self.variable_used(token.lexeme.clone());
     }
 
     fn variable_used(&mut self, name: Rc<String>) {
-        if self.var_use_table.contains_key(&name) {
-            self.var_use_table.insert(name, true);
+        if let Some((_, used)) = self.var_use_table.get_mut(&name) {
+            *used = true;
         }
     }
 
@@ -64,12 +184,16 @@ impl Resolver {
     ) -> Result<(), LoxError> {
         let pre = self.function_type;
         self.function_type = function_type;
-        self.resolve_statements(&statement.body)?;
+        self.params_define(&statement.params);
+        self.function_depth += 1;
+        let result = self.resolve_statements(&statement.body);
+        self.function_depth -= 1;
         self.function_type = pre;
-        Ok(())
+        result
     }
 
     fn resolve_statements(&mut self, statements: &[Statement]) -> Result<(), LoxError> {
+        self.dead_stores.extend(dead_stores(statements));
         for statement in statements {
             statement.accept(self)?;
         }
@@ -84,7 +208,10 @@ impl ExprVisitor<(), LoxError> for Resolver {
         &mut self,
         assign_expression: &super::expr::AssignExpression,
     ) -> Result<(), LoxError> {
-        self.resolve_expression(&assign_expression.value)
+        self.resolve_expression(&assign_expression.value)?;
+        self.variable_assigned(&assign_expression.name.lexeme);
+        self.assignments.push(assign_expression.name.clone());
+        Ok(())
     }
 
     fn visit_binary_expression(
@@ -113,6 +240,44 @@ impl ExprVisitor<(), LoxError> for Resolver {
         self.resolve_expression(&get_expression.object)
     }
 
+    fn visit_array_expression(
+        &mut self,
+        array_expression: &super::expr::ArrayExpression,
+    ) -> Result<(), LoxError> {
+        for element in &array_expression.elements {
+            self.resolve_expression(element)?;
+        }
+        Ok(())
+    }
+
+    fn visit_map_expression(
+        &mut self,
+        map_expression: &super::expr::MapExpression,
+    ) -> Result<(), LoxError> {
+        for (key, value) in &map_expression.entries {
+            self.resolve_expression(key)?;
+            self.resolve_expression(value)?;
+        }
+        Ok(())
+    }
+
+    fn visit_index_expression(
+        &mut self,
+        index_expression: &super::expr::IndexExpression,
+    ) -> Result<(), LoxError> {
+        self.resolve_expression(&index_expression.object)?;
+        self.resolve_expression(&index_expression.index)
+    }
+
+    fn visit_index_set_expression(
+        &mut self,
+        index_set_expression: &super::expr::IndexSetExpression,
+    ) -> Result<(), LoxError> {
+        self.resolve_expression(&index_set_expression.object)?;
+        self.resolve_expression(&index_set_expression.index)?;
+        self.resolve_expression(&index_set_expression.value)
+    }
+
     fn visit_grouping_expression(
         &mut self,
         grouping_expression: &super::expr::GroupingExpression,
@@ -139,7 +304,7 @@ impl ExprVisitor<(), LoxError> for Resolver {
         &mut self,
         set_expression: &super::expr::SetExpression,
     ) -> Result<(), LoxError> {
-        self.resolve_expression(&set_expression.object);
+        self.resolve_expression(&set_expression.object)?;
         self.resolve_expression(&set_expression.value)
     }
 
@@ -147,7 +312,19 @@ impl ExprVisitor<(), LoxError> for Resolver {
         &mut self,
         super_expression: &super::expr::SuperExpression,
     ) -> Result<(), LoxError> {
-        todo!()
+        if ClassType::Subclass != self.class_type {
+            return Err(LoxError::ParseError {
+                position: super_expression.keyword.position,
+                end: super_expression.keyword.end,
+                lexeme: super_expression.keyword.lexeme.clone(),
+                token_type: super_expression.keyword.token_type,
+                msg: String::from(
+                    "Keyword `super` can only be used in methods of a class with a superclass.",
+                ),
+            });
+        }
+
+        Ok(())
     }
 
     fn visit_self_expression(
@@ -160,6 +337,7 @@ impl ExprVisitor<(), LoxError> for Resolver {
         {
             return Err(LoxError::ParseError {
                 position: self_expression.keyword.position,
+                end: self_expression.keyword.end,
                 lexeme: self_expression.keyword.lexeme.clone(),
                 token_type: self_expression.keyword.token_type,
                 msg: String::from(
@@ -191,7 +369,7 @@ impl ExprVisitor<(), LoxError> for Resolver {
         &mut self,
         variable_expression: &super::expr::VariableExpression,
     ) -> Result<(), LoxError> {
-        self.variable_used(variable_expression.name.lexeme.clone());
+        self.variable_read(&variable_expression.name);
         Ok(())
     }
 
@@ -199,13 +377,37 @@ impl ExprVisitor<(), LoxError> for Resolver {
         &mut self,
         lambda_expression: &super::expr::LambdaExpression,
     ) -> Result<(), LoxError> {
-        self.resolve_statements(&lambda_expression.body)
+        let pre = self.function_type;
+        self.function_type = FuncType::Lambda;
+        self.params_define(&lambda_expression.params);
+        self.function_depth += 1;
+        let result = self.resolve_statements(&lambda_expression.body);
+        self.function_depth -= 1;
+        self.function_type = pre;
+        result
+    }
+
+    fn visit_multi_assign_expression(
+        &mut self,
+        multi_assign_expression: &super::expr::MultiAssignExpression,
+    ) -> Result<(), LoxError> {
+        for value in &multi_assign_expression.values {
+            self.resolve_expression(value)?;
+        }
+        for name in &multi_assign_expression.names {
+            self.variable_assigned(&name.lexeme);
+            self.assignments.push(name.clone());
+        }
+        Ok(())
     }
 
     fn visit_operate_and_assign_expression(
         &mut self,
         operate_and_assign_expression: &super::expr::OperateAndAssignExpression,
     ) -> Result<(), LoxError> {
+        self.reads.push(operate_and_assign_expression.name.clone());
+        self.assignments
+            .push(operate_and_assign_expression.name.clone());
         self.resolve_expression(&operate_and_assign_expression.value)
     }
 }
@@ -223,19 +425,31 @@ impl StmtVisitor<(), LoxError> for Resolver {
         &mut self,
         print_statement: &super::stmt::PrintStatement,
     ) -> Result<(), LoxError> {
-        self.resolve_expression(&print_statement.expression)
+        for expression in &print_statement.expressions {
+            self.resolve_expression(expression)?;
+        }
+        Ok(())
+    }
+
+    fn visit_assert_statement(
+        &mut self,
+        assert_statement: &super::stmt::AssertStatement,
+    ) -> Result<(), LoxError> {
+        self.resolve_expression(&assert_statement.condition)
     }
 
     fn visit_var_statement(
         &mut self,
         var_statement: &super::stmt::VarStatement,
     ) -> Result<(), LoxError> {
-        self.variable_define(var_statement.name.lexeme.clone());
+        self.variable_define(&var_statement.name);
         if let Some(init) = &var_statement.initializer {
-            self.resolve_expression(init)
+            self.resolve_expression(init)?;
+            self.variable_assigned(&var_statement.name.lexeme);
         } else {
-            Ok(())
+            self.variable_declared_unassigned(var_statement.name.lexeme.clone());
         }
+        Ok(())
     }
 
     fn visit_multi_var_statement(
@@ -288,6 +502,7 @@ impl StmtVisitor<(), LoxError> for Resolver {
         if !self.is_in_while {
             Err(LoxError::ParseError {
                 position: continue_statement.token.position,
+                end: continue_statement.token.end,
                 lexeme: continue_statement.token.lexeme.clone(),
                 token_type: continue_statement.token.token_type,
                 msg: String::from("`continue` can only be used in `while` or `for` statements"),
@@ -304,6 +519,7 @@ impl StmtVisitor<(), LoxError> for Resolver {
         if !self.is_in_while {
             Err(LoxError::ParseError {
                 position: break_statement.token.position,
+                end: break_statement.token.end,
                 lexeme: break_statement.token.lexeme.clone(),
                 token_type: break_statement.token.token_type,
                 msg: String::from("`break` can only be used in `while` or `for` statements"),
@@ -317,6 +533,19 @@ impl StmtVisitor<(), LoxError> for Resolver {
         &mut self,
         function_statement: &super::stmt::FunctionStatement,
     ) -> Result<(), LoxError> {
+        self.declared.insert(function_statement.name.lexeme.clone());
+        self.function_names
+            .insert(function_statement.name.lexeme.clone());
+        if !function_statement
+            .params
+            .iter()
+            .any(|param| param.lexeme == function_statement.name.lexeme)
+            && always_recurses(&function_statement.body, &function_statement.name.lexeme)
+                == Some(true)
+        {
+            self.recursive_functions
+                .push(function_statement.name.clone());
+        }
         self.resolve_function(function_statement, function_statement.function_type)
     }
 
@@ -327,6 +556,7 @@ impl StmtVisitor<(), LoxError> for Resolver {
         if let FuncType::Main = self.function_type {
             Err(LoxError::ParseError {
                 position: return_statement.key_word.position,
+                end: return_statement.key_word.end,
                 lexeme: return_statement.key_word.lexeme.clone(),
                 token_type: return_statement.key_word.token_type,
                 msg: String::from("`return` can only be used in a function."),
@@ -335,6 +565,7 @@ impl StmtVisitor<(), LoxError> for Resolver {
             if let FuncType::Initializer = self.function_type {
                 return Err(LoxError::ParseError {
                     position: return_statement.key_word.position,
+                    end: return_statement.key_word.end,
                     lexeme: return_statement.key_word.lexeme.clone(),
                     token_type: return_statement.key_word.token_type,
                     msg: String::from("function `__init__` can not return value."),
@@ -350,8 +581,28 @@ impl StmtVisitor<(), LoxError> for Resolver {
         &mut self,
         class_statement: &super::stmt::ClassStatement,
     ) -> Result<(), LoxError> {
+        self.declared.insert(class_statement.name.lexeme.clone());
+        self.function_names
+            .insert(class_statement.name.lexeme.clone());
+        if let Some(Expression::VariableExpression(superclass)) = &class_statement.superclass {
+            if superclass.name.lexeme == class_statement.name.lexeme {
+                return Err(LoxError::ParseError {
+                    position: superclass.name.position,
+                    end: superclass.name.end,
+                    lexeme: superclass.name.lexeme.clone(),
+                    token_type: superclass.name.token_type,
+                    msg: format!("Class `{}` can not extend itself.", superclass.name.lexeme),
+                });
+            }
+        }
         let pre = self.class_type;
-        self.class_type = ClassType::Class;
+        self.class_type = match &class_statement.superclass {
+            Some(superclass) => {
+                self.resolve_expression(superclass)?;
+                ClassType::Subclass
+            }
+            None => ClassType::Class,
+        };
         for method in &class_statement.methods {
             if let Statement::FunctionStatement(m) = method {
                 let mut func_type = FuncType::Method;
@@ -367,6 +618,261 @@ impl StmtVisitor<(), LoxError> for Resolver {
                 self.resolve_function(sm, sm.function_type)?;
             }
         }
+        for s_field in &class_statement.static_fields {
+            if let Statement::MultiVarStatement(sf) = s_field {
+                for var in &sf.vars {
+                    if let Statement::VarStatement(var) = var {
+                        if let Some(init) = &var.initializer {
+                            self.resolve_expression(init)?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_comment_statement(
+        &mut self,
+        _comment_statement: &super::stmt::CommentStatement,
+    ) -> Result<(), LoxError> {
         Ok(())
     }
 }
+
+// The recursion check only follows code that runs unconditionally, so it
+// never flags a function with a reachable base case. It gives
+// `Some(true)` once `name` is certainly called, `Some(false)` once control
+// may leave or branch, and `None` when the statements just fall through.
+fn always_recurses(statements: &[Statement], name: &Rc<String>) -> Option<bool> {
+    for statement in statements {
+        let result = match statement {
+            Statement::ExpressionStatement(s) => calls(&s.expression, name).then_some(true),
+            Statement::PrintStatement(s) => {
+                s.expressions.iter().any(|e| calls(e, name)).then_some(true)
+            }
+            Statement::AssertStatement(s) => calls(&s.condition, name).then_some(true),
+            Statement::VarStatement(s) => s
+                .initializer
+                .as_ref()
+                .is_some_and(|init| calls(init, name))
+                .then_some(true),
+            Statement::MultiVarStatement(s) => always_recurses(&s.vars, name),
+            Statement::BlockStatement(s) => always_recurses(&s.statements, name),
+            Statement::FunctionStatement(_) => None,
+            Statement::ReturnStatement(s) => {
+                Some(s.value.as_ref().is_some_and(|value| calls(value, name)))
+            }
+            _ => Some(false),
+        };
+        if result.is_some() {
+            return result;
+        }
+    }
+    None
+}
+
+fn calls(expression: &Expression, name: &Rc<String>) -> bool {
+    match expression {
+        Expression::CallExpression(e) => {
+            matches!(e.callee.as_ref(), Expression::VariableExpression(v) if &v.name.lexeme == name)
+                || calls(&e.callee, name)
+                || e.arguments.iter().any(|arg| calls(arg, name))
+        }
+        Expression::AssignExpression(e) => calls(&e.value, name),
+        Expression::BinaryExpression(e) => calls(&e.left, name) || calls(&e.right, name),
+        Expression::GetExpression(e) => calls(&e.object, name),
+        Expression::GroupingExpression(e) => calls(&e.expression, name),
+        Expression::LogicalExpression(e) => calls(&e.left, name),
+        Expression::SetExpression(e) => calls(&e.object, name) || calls(&e.value, name),
+        Expression::TernaryExpression(e) => calls(&e.cmp, name),
+        Expression::UnaryExpression(e) => calls(&e.right, name),
+        Expression::OperateAndAssignExpression(e) => calls(&e.value, name),
+        Expression::MultiAssignExpression(e) => e.values.iter().any(|v| calls(v, name)),
+        Expression::ArrayExpression(e) => e.elements.iter().any(|v| calls(v, name)),
+        Expression::MapExpression(e) => e
+            .entries
+            .iter()
+            .any(|(k, v)| calls(k, name) || calls(v, name)),
+        Expression::IndexExpression(e) => calls(&e.object, name) || calls(&e.index, name),
+        Expression::IndexSetExpression(e) => {
+            calls(&e.object, name) || calls(&e.index, name) || calls(&e.value, name)
+        }
+        _ => false,
+    }
+}
+
+// A store is dead when a later statement of the same block assigns to the
+// variable again before anything reads it. Only assignments in straight-line
+// code are followed, and any other statement ends the search.
+fn dead_stores(statements: &[Statement]) -> Vec<Token> {
+    let mut pending: Vec<&Token> = vec![];
+    let mut dead = vec![];
+    for statement in statements {
+        match statement {
+            Statement::ExpressionStatement(s) => {
+                pending.retain(|token| !may_read(&s.expression, &token.lexeme));
+                if let Expression::AssignExpression(e) = &s.expression {
+                    if let Some(i) = pending.iter().position(|t| t.lexeme == e.name.lexeme) {
+                        dead.push(pending.remove(i).clone());
+                    }
+                    pending.push(&e.name);
+                }
+            }
+            Statement::PrintStatement(s) => pending.retain(|token| {
+                !s.expressions
+                    .iter()
+                    .any(|expression| may_read(expression, &token.lexeme))
+            }),
+            Statement::AssertStatement(s) => {
+                pending.retain(|token| !may_read(&s.condition, &token.lexeme))
+            }
+            _ => pending.clear(),
+        }
+    }
+    dead
+}
+
+// Whether evaluating `expression` may read `name`. Anything that can run
+// other code, like a call, is taken to read every variable.
+fn may_read(expression: &Expression, name: &Rc<String>) -> bool {
+    match expression {
+        Expression::VariableExpression(e) => &e.name.lexeme == name,
+        Expression::LiteralExpression(_) => false,
+        Expression::AssignExpression(e) => may_read(&e.value, name),
+        Expression::BinaryExpression(e) => may_read(&e.left, name) || may_read(&e.right, name),
+        Expression::GroupingExpression(e) => may_read(&e.expression, name),
+        Expression::LogicalExpression(e) => may_read(&e.left, name) || may_read(&e.right, name),
+        Expression::TernaryExpression(e) => {
+            may_read(&e.cmp, name)
+                || may_read(&e.true_value, name)
+                || may_read(&e.false_value, name)
+        }
+        Expression::UnaryExpression(e) => may_read(&e.right, name),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Resolver;
+    use crate::rlox::error::LoxError;
+    use crate::rlox::parser::Parser;
+    use crate::rlox::scanner::Scanner;
+
+    fn resolve(source: &str) -> Result<Resolver, LoxError> {
+        let mut scanner = Scanner::new(source.to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.tokens).parse().unwrap();
+        let mut resolver = Resolver::new();
+        resolver.resolve_statements(&statements)?;
+        Ok(resolver)
+    }
+
+    /// The messages of the problems the resolver finds in `source`.
+    fn problems(source: &str) -> Vec<String> {
+        resolve(source)
+            .unwrap()
+            .problems()
+            .into_iter()
+            .map(|(_, msg)| msg)
+            .collect()
+    }
+
+    #[test]
+    fn reading_an_unassigned_variable_warns() {
+        let read = "Variable `x` is read before being assigned".to_string();
+        assert!(problems("let x; print x;").contains(&read));
+        assert!(!problems("let x; x = 1; print x;").contains(&read));
+    }
+
+    #[test]
+    fn returning_a_value_from_an_initializer_is_an_error() {
+        match resolve("class C {\n  __init__() { return 1; }\n}") {
+            Err(LoxError::ParseError {
+                position, lexeme, ..
+            }) => {
+                assert_eq!(position, (2, 15));
+                assert_eq!(*lexeme, "return");
+            }
+            _ => panic!("expected an error at `return`"),
+        }
+    }
+
+    #[test]
+    fn error_in_the_object_of_a_field_assignment_is_reported() {
+        match resolve("func f() { self.x = 1; }") {
+            Err(LoxError::ParseError { position, msg, .. }) => {
+                assert_eq!(position, (1, 11));
+                assert!(msg.starts_with("Keyword `self` can only be used in method"));
+            }
+            _ => panic!("expected an error at `self`"),
+        }
+    }
+
+    #[test]
+    fn unused_variables_are_reported_in_source_order() {
+        for _ in 0..10 {
+            let resolver = resolve("{\n  let zeta = 1;\n  let alpha = 2; let mid = 3;\n}").unwrap();
+            let problems = resolver
+                .problems()
+                .into_iter()
+                .map(|(token, msg)| (token.position, msg))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                problems,
+                vec![
+                    ((2, 6), "Unused variable `zeta`".to_string()),
+                    ((3, 6), "Unused variable `alpha`".to_string()),
+                    ((3, 21), "Unused variable `mid`".to_string()),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn strict_mode_turns_warnings_into_errors() {
+        let source = "{ let unused = 1; let x; print x; }\nfunc f() {}\nf = 2;\nprint missing;";
+        let mut scanner = Scanner::new(source.to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.tokens).parse().unwrap();
+        std::env::set_var("RLOX_RUN_MODE", "F");
+
+        assert!(Resolver::new().resolve(&statements).is_ok());
+
+        let mut resolver = Resolver::new();
+        resolver.is_strict = true;
+        let errors = resolver.resolve(&statements).unwrap_err();
+        let messages = errors
+            .iter()
+            .map(|err| match err {
+                LoxError::ParseError { msg, .. } => msg.as_str(),
+                _ => panic!("expected parse errors"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "Unused variable `unused`",
+                "Variable `x` is read before being assigned",
+                "Assigning to function `f`",
+                "Undefined variable `missing`.",
+            ]
+        );
+    }
+
+    #[test]
+    fn function_that_always_calls_itself_warns() {
+        let warning = "Function `f` always calls itself, possible infinite recursion".to_string();
+        assert!(problems("func f() { return f(); }").contains(&warning));
+        let terminating = "func f(n) { if (n == 0) return 0; return f(n - 1); }";
+        assert!(!problems(terminating).contains(&warning));
+    }
+
+    #[test]
+    fn overwritten_store_is_a_dead_store() {
+        let dead = "Value assigned to `x` is never read".to_string();
+        assert!(problems("{ let x; x = 1; x = 2; print x; }").contains(&dead));
+        assert!(!problems("{ let x; x = 1; print x; x = 2; print x; }").contains(&dead));
+    }
+}
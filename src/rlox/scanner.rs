@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::{collections::HashSet, rc::Rc};
 
 use super::token::{Token, KEYWORD_MAP};
 use super::types::TokenType;
@@ -7,58 +7,70 @@ use super::error::LoxError;
 use super::types::Literal;
 
 pub struct Scanner {
-    source: String,
+    /// The source as chars, so that looking ahead is indexing rather than
+    /// walking the string from its start. Positions count chars, not bytes.
+    source: Vec<char>,
     prev_line_lines: Vec<usize>,
     pub tokens: Vec<Token>,
 
     start: usize,
     current: usize,
+    start_line: usize,
     line: usize,
+    keep_comments: bool,
+    /// Every identifier seen so far, so that all the tokens for one name
+    /// share a single `Rc<String>`.
+    names: HashSet<Rc<String>>,
 }
 
 impl Scanner {
     pub fn new(source: String) -> Self {
-        let source_lines = source
-            .split('\n')
-            .map(|v| v.to_string())
-            .collect::<Vec<String>>();
-        let all_lines = source_lines.len();
-        let mut prev_line_lines = vec![];
-
-        for line in 0..all_lines {
-            prev_line_lines.push(
-                source_lines[0..line]
-                    .iter()
-                    .map(|v| v.len() + 1)
-                    .reduce(|pre, cur| pre + cur)
-                    .unwrap_or(0),
-            );
+        let mut prev_line_lines = vec![0];
+        for line in source.split('\n') {
+            let line_start = prev_line_lines.last().unwrap() + line.chars().count() + 1;
+            prev_line_lines.push(line_start);
         }
+        prev_line_lines.pop();
 
         Self {
-            source,
+            source: source.chars().collect(),
             prev_line_lines,
             tokens: vec![],
             start: 0,
             current: 0,
+            start_line: 1,
             line: 1,
+            keep_comments: false,
+            names: HashSet::new(),
+        }
+    }
+
+    /// A scanner for tooling, which emits comments as `Comment` tokens
+    /// instead of skipping them. The parser sets them aside, and only the
+    /// formatter looks at them.
+    pub fn with_comments(source: String) -> Self {
+        Self {
+            keep_comments: true,
+            ..Self::new(source)
         }
     }
 
     #[allow(unused)]
     pub fn append_source(&mut self, source: String) {
-        self.source.push_str(&source[..])
+        self.source.extend(source.chars())
     }
 
     pub fn scan_tokens(&mut self) -> Result<(), LoxError> {
         while !self.is_at_end() {
             self.start = self.current;
+            self.start_line = self.line;
             self.scan_token()?;
         }
 
         self.tokens.push(Token::new(
             TokenType::Eof,
-            "".into(),
+            String::new(),
+            (self.line, self.start + 1),
             (self.line, self.start + 1),
         ));
 
@@ -75,6 +87,8 @@ impl Scanner {
             ')' => self.add_token(TokenType::RightParen),
             '{' => self.add_token(TokenType::LeftBrace),
             '}' => self.add_token(TokenType::RightBrace),
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
             ',' => self.add_token(TokenType::Comma),
             '.' => self.add_token(TokenType::Dot),
             '+' => {
@@ -113,7 +127,7 @@ impl Scanner {
                     TokenType::Mod
                 };
                 self.add_token(token)
-            },
+            }
             '!' => {
                 let token = if self.expected('=') {
                     self.advance();
@@ -153,9 +167,14 @@ impl Scanner {
             }
             '/' => {
                 if self.expected('/') {
-                    while self.nth(0) != '\n' || !self.is_at_end() {
+                    while self.nth(0) != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    if self.keep_comments {
+                        self.add_token(TokenType::Comment);
+                    }
+                } else if self.expected('*') {
+                    self.parse_block_comment()?;
                 } else {
                     let token = if self.expected('=') {
                         self.advance();
@@ -172,7 +191,7 @@ impl Scanner {
             '"' => self.parse_string()?,
             _ => {
                 if cur.is_ascii_digit() {
-                    self.parse_number();
+                    self.parse_number()?;
                 } else if cur.is_ascii_alphabetic() || cur == '_' {
                     self.parse_identifier();
                 } else {
@@ -186,6 +205,41 @@ impl Scanner {
         Ok(())
     }
 
+    // Block comments nest, so commenting out code that already holds one
+    // still works.
+    fn parse_block_comment(&mut self) -> Result<(), LoxError> {
+        self.advance();
+        let mut depth = 1;
+
+        while depth > 0 {
+            if self.is_at_end() {
+                return Err(LoxError::ParseTokenError {
+                    position: self.start_position(),
+                    msg: "Unterminated block comment.",
+                });
+            }
+
+            match self.advance() {
+                '\n' => self.line += 1,
+                '/' if self.nth(0) == '*' => {
+                    self.advance();
+                    depth += 1;
+                }
+                '*' if self.nth(0) == '/' => {
+                    self.advance();
+                    depth -= 1;
+                }
+                _ => (),
+            }
+        }
+
+        if self.keep_comments {
+            self.add_token(TokenType::Comment);
+        }
+
+        Ok(())
+    }
+
     fn parse_modifier(&mut self) -> Result<(), LoxError> {
         self.advance();
         while self.nth(0) != ']' && !self.is_at_end() {
@@ -201,9 +255,9 @@ impl Scanner {
 
         self.advance();
 
-        let text = &self.source[self.start..self.current];
+        let text = self.text(self.start, self.current);
 
-        let token_type = KEYWORD_MAP.get(text);
+        let token_type = KEYWORD_MAP.get(text.as_str());
 
         match token_type {
             None => {
@@ -220,6 +274,14 @@ impl Scanner {
 
     fn parse_string(&mut self) -> Result<(), LoxError> {
         while self.nth(0) != '"' && !self.is_at_end() {
+            // The escaped character is skipped too, so `\"` doesn't end the
+            // string. A backslash at the very end leaves nothing to skip.
+            if self.nth(0) == '\\' {
+                self.advance();
+                if self.is_at_end() {
+                    break;
+                }
+            }
             if self.nth(0) == '\n' {
                 self.line += 1;
             }
@@ -235,97 +297,216 @@ impl Scanner {
 
         self.advance();
 
-        self.add_token_with_literal(
-            TokenType::String,
-            Rc::new(self.source[self.start + 1..self.current - 1].to_string()).into(),
-        );
+        let string = self.unescape(&self.text(self.start + 1, self.current - 1))?;
+        self.add_token_with_literal(TokenType::String, Rc::new(string).into());
 
         Ok(())
     }
 
-    fn parse_number(&mut self) {
-        while self.nth(0).is_ascii_digit() {
+    // Decodes the escapes in a string literal, reporting a bad one at its
+    // backslash.
+    fn unescape(&self, raw: &str) -> Result<String, LoxError> {
+        let mut string = String::with_capacity(raw.len());
+        let mut chars = raw.chars().enumerate().peekable();
+
+        while let Some((i, c)) = chars.next() {
+            if c != '\\' {
+                string.push(c);
+                continue;
+            }
+
+            let error = |msg| LoxError::ParseTokenError {
+                position: self.position_of(self.start + 1 + i),
+                msg,
+            };
+
+            match chars.next().map(|(_, c)| c) {
+                Some('n') => string.push('\n'),
+                Some('t') => string.push('\t'),
+                Some('r') => string.push('\r'),
+                Some('0') => string.push('\0'),
+                Some('"') => string.push('"'),
+                Some('\\') => string.push('\\'),
+                // `\xHH` stands for the code point U+00HH, like in Latin-1.
+                Some('x') => {
+                    let digits = chars.by_ref().take(2).map(|(_, c)| c).collect::<String>();
+                    let code = match u8::from_str_radix(&digits, 16) {
+                        Ok(code) if digits.len() == 2 => code,
+                        _ => return Err(error("Invalid hexadecimal escape.")),
+                    };
+                    string.push(code as char);
+                }
+                Some('u') => {
+                    if chars.next().map(|(_, c)| c) != Some('{') {
+                        return Err(error("Invalid unicode escape."));
+                    }
+                    let mut digits = String::new();
+                    while let Some((_, c)) = chars.next_if(|(_, c)| *c != '}') {
+                        digits.push(c);
+                    }
+                    if chars.next().is_none() {
+                        return Err(error("Invalid unicode escape."));
+                    }
+                    let c = u32::from_str_radix(&digits, 16)
+                        .ok()
+                        .filter(|_| (1..=6).contains(&digits.len()))
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| error("Invalid unicode escape."))?;
+                    string.push(c);
+                }
+                _ => return Err(error("Unknown escape sequence.")),
+            }
+        }
+
+        Ok(string)
+    }
+
+    fn parse_number(&mut self) -> Result<(), LoxError> {
+        if self.source[self.start] == '0' {
+            match self.nth(0) {
+                'x' => return self.parse_radix_number(16, "Invalid hexadecimal literal."),
+                'b' => return self.parse_radix_number(2, "Invalid binary literal."),
+                'o' => return self.parse_radix_number(8, "Invalid octal literal."),
+                _ => (),
+            }
+        }
+
+        while self.nth(0).is_ascii_digit() || self.nth(0) == '_' {
             self.advance();
         }
 
         if self.nth(0) == '.' && self.nth(1).is_ascii_digit() {
             self.advance();
 
-            while self.nth(0).is_ascii_digit() {
+            while self.nth(0).is_ascii_digit() || self.nth(0) == '_' {
                 self.advance();
             }
         }
-        self.add_token_with_literal(
-            TokenType::Number,
-            self.source[self.start..self.current]
-                .parse::<f64>()
-                .unwrap()
-                .into(),
-        );
+
+        let digits = self.strip_separators(&self.text(self.start, self.current))?;
+        // Too many digits parse as infinity, which arithmetic may not make
+        // either.
+        let value = digits.parse::<f64>().unwrap();
+        if !value.is_finite() {
+            return Err(LoxError::ParseTokenError {
+                position: self.start_position(),
+                msg: "Numeric result out of range.",
+            });
+        }
+        self.add_token_with_literal(TokenType::Number, value.into());
+        Ok(())
+    }
+
+    // `_` may separate digits, as in `1_000_000`, but it must sit between
+    // two of them.
+    fn strip_separators(&self, digits: &str) -> Result<String, LoxError> {
+        let chars = digits.chars().collect::<Vec<_>>();
+        let misplaced = chars.iter().enumerate().any(|(i, c)| {
+            *c == '_'
+                && !(i > 0
+                    && chars[i - 1].is_ascii_alphanumeric()
+                    && chars.get(i + 1).is_some_and(|c| c.is_ascii_alphanumeric()))
+        });
+
+        if misplaced {
+            return Err(LoxError::ParseTokenError {
+                position: self.start_position(),
+                msg: "Misplaced digit separator.",
+            });
+        }
+
+        Ok(digits.replace('_', ""))
+    }
+
+    // Prefixed integer literals like `0xFF`, `0b1010` and `0o17`. Every
+    // alphanumeric character after the prefix belongs to the literal, so a
+    // stray digit like the `2` in `0b102` is an error rather than a new token.
+    fn parse_radix_number(&mut self, radix: u32, msg: &'static str) -> Result<(), LoxError> {
+        self.advance();
+        while self.nth(0).is_ascii_alphanumeric() || self.nth(0) == '_' {
+            self.advance();
+        }
+
+        let digits = self.strip_separators(&self.text(self.start + 2, self.current))?;
+        let value = u64::from_str_radix(&digits, radix).map_err(|_| LoxError::ParseTokenError {
+            position: self.start_position(),
+            msg,
+        })?;
+        self.add_token_with_literal(TokenType::Number, (value as f64).into());
+        Ok(())
     }
 
     fn parse_identifier(&mut self) {
         while self.nth(0).is_ascii_alphanumeric() || self.nth(0) == '_' {
             self.advance();
         }
-        let text = &self.source[self.start..self.current];
+        let text = self.text(self.start, self.current);
 
-        let token_type = KEYWORD_MAP.get(text);
+        let token_type = KEYWORD_MAP.get(text.as_str());
 
         match token_type {
-            None => self.add_token(TokenType::Identifier),
+            None => {
+                let name = self.intern(text);
+                self.tokens.push(Token::new(
+                    TokenType::Identifier,
+                    name,
+                    self.start_position(),
+                    self.end_position(),
+                ));
+            }
             Some(token_type) => self.add_token(*token_type),
         }
     }
 
+    fn intern(&mut self, text: String) -> Rc<String> {
+        if let Some(name) = self.names.get(&text) {
+            return name.clone();
+        }
+        let name = Rc::new(text);
+        self.names.insert(name.clone());
+        name
+    }
+
     #[inline]
     fn is_at_end(&self) -> bool {
         self.current >= self.source.len()
     }
 
     fn nth(&self, n: usize) -> char {
-        if self.current + n >= self.source.len() {
-            return '\0';
-        }
-        self.source.chars().nth(self.current + n).unwrap()
+        self.source.get(self.current + n).copied().unwrap_or('\0')
     }
 
     fn advance(&mut self) -> char {
         self.current += 1;
-        self.source.chars().nth(self.current - 1).unwrap()
+        self.source[self.current - 1]
     }
 
     fn expected(&self, expected: char) -> bool {
-        if self.is_at_end() {
-            return false;
-        }
-
-        if self.source.chars().nth(self.current).unwrap() != expected {
-            return false;
-        }
+        self.source.get(self.current) == Some(&expected)
+    }
 
-        true
+    fn text(&self, start: usize, end: usize) -> String {
+        self.source[start..end].iter().collect()
     }
 
     fn add_token(&mut self, token_type: TokenType) {
-        let text = &self.source[self.start..self.current];
+        let text = self.text(self.start, self.current);
         // let pre_lines_len = self.source_lines[0..self.line - 1]
         //     .iter()
         //     .map(|v| v.len() + 1)
         //     .reduce(|pre, cur| pre + cur)
         //     .unwrap_or(0);
 
-        let pre_lines_len = self.prev_line_lines[self.line - 1];
-
         self.tokens.push(Token::new(
             token_type,
-            text.into(),
-            (self.line, self.start - pre_lines_len),
+            text,
+            self.start_position(),
+            self.end_position(),
         ));
     }
 
     fn add_token_with_literal(&mut self, token_type: TokenType, literal: Literal) {
-        let text = &self.source[self.start..self.current];
+        let text = self.text(self.start, self.current);
 
         // let pre_lines_len = self.source_lines[0..self.line - 1]
         //     .iter()
@@ -333,13 +514,219 @@ impl Scanner {
         //     .reduce(|pre, cur| pre + cur)
         //     .unwrap_or(0);
 
-        let pre_lines_len = self.prev_line_lines[self.line - 1];
-
         self.tokens.push(Token::with_literal(
             token_type,
-            text.into(),
+            text,
             Some(literal),
-            (self.line, self.start - pre_lines_len),
+            self.start_position(),
+            self.end_position(),
         ));
     }
+
+    // A string can span several lines, so the start of a token is measured
+    // from the line it began on rather than the current one.
+    fn start_position(&self) -> (usize, usize) {
+        let pre_lines_len = self.prev_line_lines[self.start_line - 1];
+        (self.start_line, self.start - pre_lines_len)
+    }
+
+    fn position_of(&self, offset: usize) -> (usize, usize) {
+        let line = self
+            .prev_line_lines
+            .partition_point(|line_start| *line_start <= offset);
+        (line, offset - self.prev_line_lines[line - 1])
+    }
+
+    fn end_position(&self) -> (usize, usize) {
+        let pre_lines_len = self.prev_line_lines[self.line - 1];
+        (self.line, self.current - pre_lines_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::Scanner;
+    use crate::rlox::error::LoxError;
+    use crate::rlox::token::Token;
+    use crate::rlox::types::{Literal, TokenType};
+
+    fn scan(source: &str) -> Result<Vec<Token>, LoxError> {
+        let mut scanner = Scanner::new(source.to_string());
+        scanner.scan_tokens()?;
+        Ok(scanner.tokens)
+    }
+
+    fn string(source: &str) -> Result<String, LoxError> {
+        match scan(source)?.remove(0).literal {
+            Some(Literal::String(string)) => Ok(string.to_string()),
+            literal => panic!("expected a string but got {:?}", literal),
+        }
+    }
+
+    fn number(source: &str) -> Result<f64, LoxError> {
+        match scan(source)?.remove(0).literal {
+            Some(Literal::Number(number)) => Ok(number),
+            literal => panic!("expected a number but got {:?}", literal),
+        }
+    }
+
+    fn token_error(source: &str) -> &'static str {
+        match scan(source) {
+            Err(LoxError::ParseTokenError { msg, .. }) => msg,
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn hex_and_unicode_escapes() {
+        assert_eq!(string(r#""\x41""#).unwrap(), "A");
+        assert_eq!(string(r#""\xFF""#).unwrap(), "\u{FF}");
+        let emoji = string(r#""\u{1F600}""#).unwrap();
+        assert_eq!(emoji, "\u{1F600}");
+        assert_eq!(emoji.chars().count(), 1);
+        assert!(string(r#""\u{110000}""#).is_err());
+        assert!(string(r#""\x4""#).is_err());
+    }
+
+    #[test]
+    fn comment_tokens_keep_their_text_and_position() {
+        let source = "// head\nlet a = 1; // a\nprint a; // tail\n";
+        let mut scanner = Scanner::with_comments(source.to_string());
+        scanner.scan_tokens().unwrap();
+        let comments = scanner
+            .tokens
+            .iter()
+            .filter(|token| token.token_type == TokenType::Comment)
+            .map(|token| (token.lexeme.to_string(), token.position))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            comments,
+            vec![
+                ("// head".to_string(), (1, 0)),
+                ("// a".to_string(), (2, 11)),
+                ("// tail".to_string(), (3, 9)),
+            ]
+        );
+
+        let tokens = scan(source).unwrap();
+        assert!(tokens.iter().all(|t| t.token_type != TokenType::Comment));
+    }
+
+    #[test]
+    fn block_comments_nest_and_count_lines() {
+        let tokens = scan("/* outer /* inner */ still\ncommented */ x").unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(*tokens[0].lexeme, "x");
+        assert_eq!(tokens[0].position, (2, 13));
+
+        match scan("let a;\n  /* open /* nested */\n") {
+            Err(LoxError::ParseTokenError { position, msg }) => {
+                assert_eq!(position, (2, 2));
+                assert_eq!(msg, "Unterminated block comment.");
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn backslash_at_the_end_and_escaped_newlines() {
+        match scan("\"abc\\") {
+            Err(LoxError::ParseTokenError { msg, .. }) => assert_eq!(msg, "Unterminated String."),
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        // an escaped newline is skipped with its backslash, and still
+        // counts as a line
+        match scan("\"a\\\n") {
+            Err(LoxError::ParseTokenError { position, .. }) => assert_eq!(position.0, 2),
+            result => panic!("unexpected result {:?}", result),
+        }
+        let tokens = scan("\"a\\\\\nb\" x").unwrap();
+        assert_eq!(tokens[1].position, (2, 3));
+    }
+
+    #[test]
+    fn hexadecimal_binary_and_octal_literals() {
+        assert_eq!(number("0xFF").unwrap(), 255.0);
+        assert_eq!(number("0b1010").unwrap(), 10.0);
+        assert_eq!(number("0o17").unwrap(), 15.0);
+        assert_eq!(number("0").unwrap(), 0.0);
+        assert_eq!(number("0.5").unwrap(), 0.5);
+        assert_eq!(token_error("0b102"), "Invalid binary literal.");
+        assert_eq!(token_error("0xG"), "Invalid hexadecimal literal.");
+        assert_eq!(token_error("0o8"), "Invalid octal literal.");
+    }
+
+    #[test]
+    fn digit_separators_sit_between_digits() {
+        assert_eq!(number("1_000_000").unwrap(), 1_000_000.0);
+        assert_eq!(number("1.234_567").unwrap(), 1.234_567);
+        assert_eq!(number("0xFF_FF").unwrap(), 65535.0);
+        assert_eq!(number("1.5").unwrap(), 1.5);
+        for source in ["5_", "5__0", "1_.5", "0x_F"] {
+            assert_eq!(
+                token_error(source),
+                "Misplaced digit separator.",
+                "{source}"
+            );
+        }
+        // a leading `_` makes an identifier
+        assert_eq!(*scan("_5").unwrap()[0].lexeme, "_5");
+    }
+
+    #[test]
+    fn positions_count_chars_on_every_line() {
+        let line = "let s = \"héllo\"; print s;\n";
+        let tokens = scan(&line.repeat(10_000)).unwrap();
+        assert_eq!(tokens.len(), 10_000 * 8 + 1);
+
+        let last = &tokens[tokens.len() - 9..];
+        let found = last
+            .iter()
+            .map(|token| (token.lexeme.to_string(), token.position))
+            .collect::<Vec<_>>();
+        let expected = [
+            ("let", 0),
+            ("s", 4),
+            ("=", 6),
+            ("\"héllo\"", 8),
+            (";", 15),
+            ("print", 17),
+        ];
+        for ((lexeme, position), (want, column)) in found.iter().zip(expected) {
+            assert_eq!(lexeme, want);
+            assert_eq!(*position, (10_000, column));
+        }
+    }
+
+    #[test]
+    fn line_comment_at_the_end_of_input() {
+        let tokens = scan("print 1; // done").unwrap();
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(scan("//").unwrap().len(), 1);
+
+        let mut scanner = Scanner::with_comments("x //".to_string());
+        scanner.scan_tokens().unwrap();
+        assert_eq!(*scanner.tokens[1].lexeme, "//");
+    }
+
+    #[test]
+    fn repeated_identifiers_share_one_string() {
+        let tokens = scan(&"count = count + 1;\n".repeat(100)).unwrap();
+        let names = tokens
+            .iter()
+            .filter(|token| *token.lexeme == "count")
+            .collect::<Vec<_>>();
+        assert_eq!(names.len(), 200);
+        assert!(names
+            .iter()
+            .all(|token| Rc::ptr_eq(&token.lexeme, &names[0].lexeme)));
+
+        let keywords = scan("print print").unwrap();
+        assert!(keywords
+            .iter()
+            .all(|t| t.token_type != TokenType::Identifier));
+    }
 }
@@ -58,15 +58,17 @@ macro_rules! stmt {
 
 stmt! {
     ExpressionStatement { expression: Expression, end: Token },
-    PrintStatement { expression: Expression, keyword: Token },
+    PrintStatement { expressions: Vec<Expression>, keyword: Token },
+    AssertStatement { condition: Expression, keyword: Token },
     VarStatement { name: Token, initializer: Option<Expression> },
     MultiVarStatement { vars: Vec<Statement> },
     BlockStatement { statements: Vec<Statement> },
-    BranchStatement { condition: Expression, then_branch: Box<Statement>, else_branch: Option<Box<Statement>> },
+    BranchStatement { keyword: Token, condition: Expression, then_branch: Box<Statement>, else_branch: Option<Box<Statement>> },
     WhileStatement { condition: Expression, body: Box<Statement>, increment: Option<Box<Statement>> },
     ContinueStatement { token: Token },
     BreakStatement { token: Token },
     FunctionStatement { name: Token, params: Vec<Token>, body: Vec<Statement>, function_type: FuncType },
     ReturnStatement { key_word: Token, value: Option<Expression> },
-    ClassStatement { name: Token, methods: Vec<Statement>, static_methods: Vec<Statement> }
+    ClassStatement { name: Token, superclass: Option<Expression>, methods: Vec<Statement>, static_methods: Vec<Statement>, static_fields: Vec<Statement> },
+    CommentStatement { token: Token, trailing: bool }
 }
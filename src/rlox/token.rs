@@ -25,10 +25,12 @@ lazy_static! {
         ("true", TokenType::True),
         ("let", TokenType::Let),
         ("while", TokenType::While),
+        ("when", TokenType::When),
         ("continue", TokenType::Continue),
         ("break", TokenType::Break),
         ("#[static]", TokenType::Static),
-        ("extend", TokenType::Extend)
+        ("extend", TokenType::Extend),
+        ("assert", TokenType::Assert)
     ]);
 }
 
@@ -38,35 +40,72 @@ pub struct Token {
     pub lexeme: Rc<String>,
     pub literal: Option<Literal>,
     pub position: (usize, usize),
+    /// Where the token ends, one past its last column.
+    pub end: (usize, usize),
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, lexeme: String, position: (usize, usize)) -> Self {
+    pub fn new(
+        token_type: TokenType,
+        lexeme: impl Into<Rc<String>>,
+        position: (usize, usize),
+        end: (usize, usize),
+    ) -> Self {
         Self {
             token_type,
-            lexeme: Rc::new(lexeme),
+            lexeme: lexeme.into(),
             position,
+            end,
             literal: None,
         }
     }
 
+    /// The text shown to users, which falls back to the token type's symbol
+    /// for synthetic tokens that carry no lexeme.
+    pub fn display_lexeme(&self) -> Rc<String> {
+        if self.lexeme.is_empty() {
+            Rc::new(self.token_type.symbol().into())
+        } else {
+            self.lexeme.clone()
+        }
+    }
+
     pub fn with_literal(
         token_type: TokenType,
         lexeme: String,
         literal: Option<Literal>,
         position: (usize, usize),
+        end: (usize, usize),
     ) -> Self {
         Self {
             token_type,
             lexeme: Rc::new(lexeme),
             literal,
             position,
+            end,
         }
     }
 }
 
 impl Display for Token {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.lexeme)
+        if self.lexeme.is_empty() {
+            write!(f, "{}", self.token_type.symbol())
+        } else {
+            write!(f, "{}", self.lexeme)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Token;
+    use crate::rlox::types::TokenType;
+
+    #[test]
+    fn token_without_lexeme_shows_its_symbol() {
+        let token = Token::new(TokenType::GreaterEqual, String::new(), (1, 1), (1, 1));
+        assert_eq!(*token.display_lexeme(), ">=");
+        assert_eq!(token.to_string(), ">=");
     }
 }
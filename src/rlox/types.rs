@@ -1,10 +1,13 @@
 use std::{
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::HashMap,
     fmt::{Display, Formatter},
     rc::Rc,
 };
 
 use super::{
-    bytecode_interpreter::chunk::Chunk,
+    bytecode_interpreter::{chunk::Chunk, vm::VirtualMachine},
     error::{LoxError, Result},
 };
 
@@ -37,6 +40,7 @@ impl Display for FuncType {
 pub enum ClassType {
     None,
     Class,
+    Subclass,
 }
 
 impl Display for ClassType {
@@ -44,6 +48,7 @@ impl Display for ClassType {
         match self {
             ClassType::None => write!(f, ""),
             ClassType::Class => write!(f, "Class"),
+            ClassType::Subclass => write!(f, "Subclass"),
         }
     }
 }
@@ -56,6 +61,8 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
@@ -103,14 +110,108 @@ pub enum TokenType {
     True,
     Let,
     While,
+    When,
     Continue,
     Break,
     Static,
     Extend,
+    Assert,
+
+    Comment,
 
     Eof,
 }
 
+impl TokenType {
+    pub fn is_keyword(&self) -> bool {
+        use TokenType::*;
+        matches!(
+            self,
+            And | Class
+                | Else
+                | False
+                | Func
+                | For
+                | If
+                | Nil
+                | Or
+                | Print
+                | Return
+                | Super
+                | RSelf
+                | True
+                | Let
+                | While
+                | When
+                | Continue
+                | Break
+                | Extend
+                | Assert
+        )
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            TokenType::Colon => ":",
+            TokenType::LeftParen => "(",
+            TokenType::RightParen => ")",
+            TokenType::LeftBrace => "{",
+            TokenType::RightBrace => "}",
+            TokenType::LeftBracket => "[",
+            TokenType::RightBracket => "]",
+            TokenType::Comma => ",",
+            TokenType::Dot => ".",
+            TokenType::Minus => "-",
+            TokenType::Plus => "+",
+            TokenType::QuestionMark => "?",
+            TokenType::Semicolon => ";",
+            TokenType::Slash => "/",
+            TokenType::Star => "*",
+            TokenType::Mod => "%",
+            TokenType::Bang => "!",
+            TokenType::BangEqual => "!=",
+            TokenType::Equal => "=",
+            TokenType::EqualEqual => "==",
+            TokenType::Greater => ">",
+            TokenType::GreaterEqual => ">=",
+            TokenType::Less => "<",
+            TokenType::LessEqual => "<=",
+            TokenType::PlusEqual => "+=",
+            TokenType::MinusEqual => "-=",
+            TokenType::StarEqual => "*=",
+            TokenType::SlashEqual => "/=",
+            TokenType::ModEqual => "%=",
+            TokenType::Identifier => "identifier",
+            TokenType::String => "string",
+            TokenType::Number => "number",
+            TokenType::And => "and",
+            TokenType::Class => "class",
+            TokenType::Else => "else",
+            TokenType::False => "false",
+            TokenType::Func => "func",
+            TokenType::For => "for",
+            TokenType::If => "if",
+            TokenType::Nil => "nil",
+            TokenType::Or => "or",
+            TokenType::Print => "print",
+            TokenType::Return => "return",
+            TokenType::Super => "super",
+            TokenType::RSelf => "self",
+            TokenType::True => "true",
+            TokenType::Let => "let",
+            TokenType::While => "while",
+            TokenType::When => "when",
+            TokenType::Continue => "continue",
+            TokenType::Break => "break",
+            TokenType::Static => "#[static]",
+            TokenType::Extend => "extend",
+            TokenType::Assert => "assert",
+            TokenType::Comment => "comment",
+            TokenType::Eof => "end",
+        }
+    }
+}
+
 macro_rules! to_literal {
     ($(($name: ty, $literal_type: ident)), *) => {
         $(
@@ -123,16 +224,171 @@ macro_rules! to_literal {
     };
 }
 
+/// Arrays and maps are shared, so a value copied out of one still sees
+/// writes made through the other.
+pub type Array = Rc<RefCell<Vec<Literal>>>;
+pub type Map = Rc<RefCell<HashMap<MapKey, Literal>>>;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
     String(Rc<String>),
     Number(f64),
     Bool(bool),
     Function(Rc<Function>),
+    Native(Rc<NativeFunction>),
+    StringBuilder(Rc<RefCell<String>>),
+    Array(Array),
+    Map(Map),
+    Class(Rc<Class>),
+    Instance(Rc<Instance>),
+    BoundMethod(Rc<BoundMethod>),
     Nil,
 }
 
 impl Literal {
+    /// Equality for Lox's `==`. Numbers compare as IEEE 754 says, so `NaN`
+    /// is equal to nothing, itself included, and values of different types
+    /// are never equal. Mutable values, like string builders, arrays and
+    /// maps, are only equal to themselves.
+    pub fn is_equal(&self, other: &Literal) -> bool {
+        match (self, other) {
+            (Literal::Number(left), Literal::Number(right)) => left == right,
+            (Literal::String(left), Literal::String(right)) => left == right,
+            (Literal::Bool(left), Literal::Bool(right)) => left == right,
+            (Literal::Function(left), Literal::Function(right)) => left == right,
+            (Literal::Native(left), Literal::Native(right)) => left == right,
+            (Literal::StringBuilder(left), Literal::StringBuilder(right)) => {
+                Rc::ptr_eq(left, right)
+            }
+            (Literal::Array(left), Literal::Array(right)) => Rc::ptr_eq(left, right),
+            (Literal::Map(left), Literal::Map(right)) => Rc::ptr_eq(left, right),
+            (Literal::Class(left), Literal::Class(right)) => left == right,
+            (Literal::Instance(left), Literal::Instance(right)) => left == right,
+            (Literal::BoundMethod(left), Literal::BoundMethod(right)) => left == right,
+            (Literal::Nil, Literal::Nil) => true,
+            _ => false,
+        }
+    }
+
+    /// Equality for `equals()`, which compares arrays element by element,
+    /// maps entry by entry and instances of the same class field by field,
+    /// and everything else like `==` does.
+    pub fn is_deep_equal(&self, other: &Literal) -> bool {
+        self.deep_equal(other, &mut vec![])
+    }
+
+    /// `open` holds the pairs of values being compared around this one, like
+    /// in `fmt_nested`. A pair met again inside itself is taken as equal, as
+    /// any difference between the two shows up somewhere else.
+    fn deep_equal(&self, other: &Literal, open: &mut Vec<(*const (), *const ())>) -> bool {
+        let pair = match (self, other) {
+            (Literal::Array(left), Literal::Array(right)) => (
+                Rc::as_ptr(left) as *const (),
+                Rc::as_ptr(right) as *const (),
+            ),
+            (Literal::Map(left), Literal::Map(right)) => (
+                Rc::as_ptr(left) as *const (),
+                Rc::as_ptr(right) as *const (),
+            ),
+            (Literal::Instance(left), Literal::Instance(right))
+                if Rc::ptr_eq(&left.class, &right.class) =>
+            {
+                (
+                    Rc::as_ptr(left) as *const (),
+                    Rc::as_ptr(right) as *const (),
+                )
+            }
+            _ => return self.is_equal(other),
+        };
+        if pair.0 == pair.1 || open.contains(&pair) {
+            return true;
+        }
+
+        open.push(pair);
+        let equal = self.entries_equal(other, open);
+        open.pop();
+        equal
+    }
+
+    fn entries_equal(&self, other: &Literal, open: &mut Vec<(*const (), *const ())>) -> bool {
+        match (self, other) {
+            (Literal::Array(left), Literal::Array(right)) => {
+                let (left, right) = (left.borrow(), right.borrow());
+                left.len() == right.len()
+                    && left
+                        .iter()
+                        .zip(right.iter())
+                        .all(|(left, right)| left.deep_equal(right, open))
+            }
+            (Literal::Map(left), Literal::Map(right)) => {
+                let (left, right) = (left.borrow(), right.borrow());
+                left.len() == right.len()
+                    && left.iter().all(|(key, left)| {
+                        right
+                            .get(key)
+                            .is_some_and(|right| left.deep_equal(right, open))
+                    })
+            }
+            (Literal::Instance(left), Literal::Instance(right)) => {
+                let (left, right) = (left.fields.borrow(), right.fields.borrow());
+                left.len() == right.len()
+                    && left.iter().all(|(name, left)| {
+                        right
+                            .get(name)
+                            .is_some_and(|right| left.deep_equal(right, open))
+                    })
+            }
+            _ => false,
+        }
+    }
+
+    /// The kind of value, as `type()` reports it. Everything callable that
+    /// isn't a class is a `function`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Literal::String(_) => "string",
+            Literal::Number(_) => "number",
+            Literal::Bool(_) => "bool",
+            Literal::Function(_) | Literal::Native(_) | Literal::BoundMethod(_) => "function",
+            Literal::StringBuilder(_) => "string_builder",
+            Literal::Array(_) => "array",
+            Literal::Map(_) => "map",
+            Literal::Class(_) => "class",
+            Literal::Instance(_) => "instance",
+            Literal::Nil => "nil",
+        }
+    }
+
+    /// The one argument count check for calls, shared by functions, lambdas
+    /// and natives. Values that can't be called at all pass, and are
+    /// rejected when the call is made.
+    pub fn check_arity(&self, count: usize) -> Result<(), String> {
+        let (arity, callee) = match self {
+            Literal::Function(function) => (Arity::Exact(function.arity), function.describe()),
+            Literal::Native(native) => (native.arity, format!("native fn `{}`", native.name)),
+            Literal::BoundMethod(bound) => {
+                (Arity::Exact(bound.method.arity), bound.method.describe())
+            }
+            Literal::Class(class) => (
+                Arity::Exact(class.initializer().map_or(0, |init| init.arity)),
+                format!("class `{}`", class.name),
+            ),
+            _ => return Ok(()),
+        };
+
+        if arity.accepts(count) {
+            return Ok(());
+        }
+        let expected = match arity {
+            Arity::Exact(arity) => arity.to_string(),
+            Arity::Variadic(min) => format!("at least {}", min),
+        };
+        Err(format!(
+            "Expect {} arguments but got {} when calling {}.",
+            expected, count, callee
+        ))
+    }
+
     pub fn get_num(&self) -> Result<f64> {
         if let Literal::Number(num) = self {
             return Ok(*num);
@@ -191,12 +447,133 @@ impl Literal {
 
     pub fn is_true(&self) -> bool {
         match self {
-            Literal::String(_) | Literal::Number(_) | Literal::Function(_) => true,
+            Literal::String(_)
+            | Literal::Number(_)
+            | Literal::Function(_)
+            | Literal::Native(_)
+            | Literal::StringBuilder(_)
+            | Literal::Array(_)
+            | Literal::Map(_)
+            | Literal::Class(_)
+            | Literal::Instance(_)
+            | Literal::BoundMethod(_) => true,
             Literal::Bool(b) => *b,
             Literal::Nil => false,
         }
     }
 
+    /// The truthiness used under `--len-truthy`: on top of `nil` and `false`,
+    /// the number `0`, empty strings, string builders and collections are
+    /// falsy.
+    pub fn is_len_true(&self) -> bool {
+        match self {
+            Literal::String(s) => !s.is_empty(),
+            Literal::Number(n) => *n != 0.0,
+            Literal::StringBuilder(sb) => !sb.borrow().is_empty(),
+            Literal::Array(array) => !array.borrow().is_empty(),
+            Literal::Map(map) => !map.borrow().is_empty(),
+            _ => self.is_true(),
+        }
+    }
+
+    /// The order used by comparisons: numbers compare numerically and
+    /// strings lexicographically. Any other pair of values is unordered.
+    pub fn compare(&self, other: &Literal) -> Result<Ordering, &'static str> {
+        match (self, other) {
+            (Literal::Number(left), Literal::Number(right)) => left
+                .partial_cmp(right)
+                .ok_or("Operands must be comparable numbers."),
+            (Literal::String(left), Literal::String(right)) => Ok(left.cmp(right)),
+            _ => Err("Operands must be two numbers or two strings."),
+        }
+    }
+
+    /// The value at `index`, for `array[index]` and `map[key]`. A key that
+    /// isn't in a map gives `nil`.
+    pub fn get_index(&self, index: &Literal) -> std::result::Result<Literal, String> {
+        match self {
+            Literal::Array(array) => {
+                let array = array.borrow();
+                let i = array_index(index, array.len())?;
+                Ok(array[i].clone())
+            }
+            Literal::Map(map) => Ok(map
+                .borrow()
+                .get(&MapKey::new(index)?)
+                .cloned()
+                .unwrap_or(Literal::Nil)),
+            _ => Err("Only arrays and maps can be indexed.".into()),
+        }
+    }
+
+    pub fn set_index(&self, index: &Literal, value: Literal) -> std::result::Result<(), String> {
+        match self {
+            Literal::Array(array) => {
+                let mut array = array.borrow_mut();
+                let i = array_index(index, array.len())?;
+                array[i] = value;
+            }
+            Literal::Map(map) => {
+                map.borrow_mut().insert(MapKey::new(index)?, value);
+            }
+            _ => return Err("Only arrays and maps can be indexed.".into()),
+        }
+        Ok(())
+    }
+
+    /// Arrays and maps are written out entry by entry, borrowing each nested
+    /// one in turn. `open` holds the collections being written around this
+    /// one, so a collection that contains itself is cut short as `[...]` or
+    /// `{...}`, as is everything past `MAX_PRINT_DEPTH` levels.
+    fn fmt_nested(&self, f: &mut Formatter<'_>, open: &mut Vec<*const ()>) -> std::fmt::Result {
+        let ptr = match self {
+            Literal::Array(array) => Rc::as_ptr(array) as *const (),
+            Literal::Map(map) => Rc::as_ptr(map) as *const (),
+            _ => return write!(f, "{}", self),
+        };
+        if open.len() >= MAX_PRINT_DEPTH || open.contains(&ptr) {
+            return match self {
+                Literal::Array(_) => write!(f, "[...]"),
+                _ => write!(f, "{{...}}"),
+            };
+        }
+
+        open.push(ptr);
+        let result = self.fmt_entries(f, open);
+        open.pop();
+        result
+    }
+
+    fn fmt_entries(&self, f: &mut Formatter<'_>, open: &mut Vec<*const ()>) -> std::fmt::Result {
+        match self {
+            Literal::Array(array) => {
+                write!(f, "[")?;
+                for (i, element) in array.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.fmt_nested(f, open)?;
+                }
+                write!(f, "]")
+            }
+            Literal::Map(map) => {
+                let map = map.borrow();
+                let mut entries = map.iter().collect::<Vec<_>>();
+                entries.sort_by_key(|(key, _)| *key);
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", key)?;
+                    value.fmt_nested(f, open)?;
+                }
+                write!(f, "}}")
+            }
+            _ => write!(f, "{}", self),
+        }
+    }
+
     pub fn get_function(&self) -> Result<Rc<Function>> {
         if let Literal::Function(func) = self {
             return Ok(Rc::clone(func));
@@ -212,7 +589,133 @@ to_literal! {
     (bool, Bool),
     (f64, Number),
     (Rc<String>, String),
-    (Rc<Function>, Function)
+    (Rc<Function>, Function),
+    (Rc<NativeFunction>, Native),
+    (Rc<Class>, Class),
+    (Rc<Instance>, Instance),
+    (Rc<BoundMethod>, BoundMethod)
+}
+
+macro_rules! from_literal {
+    ($(($name: ty, $literal_type: ident, $expected: literal, $value: ident => $convert: expr)), *) => {
+        $(
+            impl TryFrom<Literal> for $name {
+                type Error = LoxError;
+
+                fn try_from(literal: Literal) -> Result<Self> {
+                    match literal {
+                        Literal::$literal_type($value) => Ok($convert),
+                        other => Err(LoxError::UnexpectedError {
+                            message: format!("Expect {}, but got `{}`.", $expected, other),
+                        }),
+                    }
+                }
+            }
+        ) *
+    };
+}
+
+from_literal! {
+    (f64, Number, "a number", num => num),
+    (bool, Bool, "a bool", b => b),
+    (String, String, "a string", string => string.to_string())
+}
+
+// Arrays and maps are handed out by reference, without copying what is in
+// them, so these convert from a borrowed `Literal`.
+macro_rules! from_literal_ref {
+    ($(($name: ty, $literal_type: ident, $expected: literal)), *) => {
+        $(
+            impl TryFrom<&Literal> for $name {
+                type Error = LoxError;
+
+                fn try_from(literal: &Literal) -> Result<Self> {
+                    match literal {
+                        Literal::$literal_type(value) => Ok(value.clone()),
+                        other => Err(LoxError::UnexpectedError {
+                            message: format!("Expect {}, but got `{}`.", $expected, other),
+                        }),
+                    }
+                }
+            }
+        ) *
+    };
+}
+
+from_literal_ref! {
+    (Array, Array, "an array"),
+    (Map, Map, "a map")
+}
+
+/// A value that can key a map. Only strings and numbers can, and numbers
+/// are kept by their bits, with `-0` folded into `0`, as `f64` can't be
+/// hashed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Number(u64),
+    String(Rc<String>),
+}
+
+impl MapKey {
+    pub fn new(literal: &Literal) -> std::result::Result<Self, String> {
+        match literal {
+            Literal::Number(num) if num.is_nan() => Err("Map key can't be NaN.".into()),
+            Literal::Number(num) => Ok(MapKey::Number((num + 0.0).to_bits())),
+            Literal::String(string) => Ok(MapKey::String(string.clone())),
+            other => Err(format!(
+                "Map key must be a string or a number, but got `{}`.",
+                other
+            )),
+        }
+    }
+}
+
+/// Numbers come before strings, so that maps print in a stable order.
+impl Ord for MapKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (MapKey::Number(left), MapKey::Number(right)) => {
+                f64::from_bits(*left).total_cmp(&f64::from_bits(*right))
+            }
+            (MapKey::String(left), MapKey::String(right)) => left.cmp(right),
+            (MapKey::Number(_), MapKey::String(_)) => Ordering::Less,
+            (MapKey::String(_), MapKey::Number(_)) => Ordering::Greater,
+        }
+    }
+}
+
+impl PartialOrd for MapKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Display for MapKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MapKey::Number(bits) => write!(f, "{}", f64::from_bits(*bits)),
+            MapKey::String(string) => write!(f, "{}", string),
+        }
+    }
+}
+
+/// How many arrays or maps deep printing goes before it stops.
+const MAX_PRINT_DEPTH: usize = 32;
+
+fn array_index(index: &Literal, len: usize) -> std::result::Result<usize, String> {
+    let Literal::Number(index) = index else {
+        return Err("Index must be a number.".into());
+    };
+    if index.fract() != 0.0 {
+        return Err("Index must be an integer.".into());
+    }
+    if *index < 0.0 || *index >= len as f64 {
+        return Err(format!(
+            "Index {} is out of bounds for an array of length {}.",
+            index, len
+        ));
+    }
+    Ok(*index as usize)
 }
 
 impl Display for Literal {
@@ -222,17 +725,77 @@ impl Display for Literal {
             Literal::Number(num) => write!(f, "{}", num),
             Literal::Bool(b) => write!(f, "{}", b),
             Literal::Nil => write!(f, "nil"),
-            Literal::Function(func) => {
-                if func.func_type == FuncType::Lambda {
-                    write!(f, "<func Lambda>")
-                } else {
-                    write!(f, "<func {}>", func.name)
-                }
-            }
+            Literal::Function(func) => write!(f, "{}", func),
+            Literal::Native(native) => write!(f, "<native fn {}/{}>", native.name, native.arity),
+            Literal::StringBuilder(_) => write!(f, "<string builder>"),
+            Literal::Array(_) | Literal::Map(_) => self.fmt_nested(f, &mut vec![]),
+            Literal::Class(class) => write!(f, "<class {}>", class.name),
+            Literal::Instance(instance) => write!(f, "<{} instance>", instance.class.name),
+            Literal::BoundMethod(bound) => write!(f, "{}", bound.method),
+        }
+    }
+}
+
+/// Natives get the VM so that they can call back into Lox functions.
+pub type NativeFn = fn(&mut VirtualMachine, &[Literal]) -> Result<Literal, String>;
+
+/// How many arguments a callable takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exact(usize),
+    /// At least this many, and any number more.
+    Variadic(usize),
+}
+
+impl Arity {
+    pub fn accepts(self, count: usize) -> bool {
+        match self {
+            Arity::Exact(arity) => count == arity,
+            Arity::Variadic(min) => count >= min,
+        }
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arity::Exact(arity) => write!(f, "{}", arity),
+            Arity::Variadic(min) => write!(f, "{}+", min),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NativeFunction {
+    pub name: &'static str,
+    pub arity: Arity,
+    pub function: NativeFn,
+}
+
+impl NativeFunction {
+    pub fn new(name: &'static str, arity: usize, function: NativeFn) -> Self {
+        Self {
+            name,
+            arity: Arity::Exact(arity),
+            function,
+        }
+    }
+
+    pub fn variadic(name: &'static str, min: usize, function: NativeFn) -> Self {
+        Self {
+            name,
+            arity: Arity::Variadic(min),
+            function,
         }
     }
 }
 
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
 static mut LAMBDA_ID: u32 = 0;
 
 fn gen_lambda_id() -> String {
@@ -249,6 +812,8 @@ pub struct Function {
     pub chunk: Chunk,
     pub arity: usize,
     pub func_type: FuncType,
+    /// Where a lambda is defined, as lambdas have no name to show.
+    pub position: (usize, usize),
 }
 
 impl Function {
@@ -258,9 +823,32 @@ impl Function {
             chunk,
             arity,
             func_type,
+            position: (0, 0),
+        }
+    }
+
+    /// How the function is referred to in messages for users.
+    pub fn describe(&self) -> String {
+        if self.func_type == FuncType::Lambda {
+            format!(
+                "lambda defined at [{},{}]",
+                self.position.0, self.position.1
+            )
+        } else {
+            format!("function `{}`", self.name)
         }
     }
 
+    /// The source map of this function followed by those of the functions
+    /// it defines, each under a `== name ==` header.
+    pub fn source_map(&self) -> String {
+        let mut map = format!("== {} ==\n{}", self, self.chunk.source_map());
+        for function in self.chunk.functions() {
+            map.push_str(&function.source_map());
+        }
+        map
+    }
+
     pub fn lambda_name() -> Rc<String> {
         Rc::new(gen_lambda_id())
     }
@@ -268,7 +856,13 @@ impl Function {
 
 impl Display for Function {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}( .. )", self.name)
+        match self.func_type {
+            FuncType::Lambda => write!(f, "<lambda/{}>", self.arity),
+            FuncType::Method | FuncType::StaticMethod | FuncType::Initializer => {
+                write!(f, "<method {}/{}>", self.name, self.arity)
+            }
+            _ => write!(f, "<fn {}/{}>", self.name, self.arity),
+        }
     }
 }
 
@@ -277,3 +871,244 @@ impl PartialEq for Function {
         self.name == other.name && self.arity == other.arity
     }
 }
+
+/// A class at runtime. It starts out empty when the declaration runs and
+/// the VM adds the methods and static fields one by one, which is why they
+/// sit in a `RefCell`.
+#[derive(Debug)]
+pub struct Class {
+    pub name: Rc<String>,
+    pub superclass: Option<Rc<Class>>,
+    pub methods: RefCell<HashMap<Rc<String>, Rc<Function>>>,
+    pub static_methods: RefCell<HashMap<Rc<String>, Rc<Function>>>,
+    pub static_fields: RefCell<HashMap<Rc<String>, Literal>>,
+}
+
+impl Class {
+    pub fn new(name: Rc<String>, superclass: Option<Rc<Class>>) -> Self {
+        Self {
+            name,
+            superclass,
+            methods: Default::default(),
+            static_methods: Default::default(),
+            static_fields: Default::default(),
+        }
+    }
+
+    /// Looks a method up in this class first and then up its superclasses.
+    pub fn find_method(&self, name: &Rc<String>) -> Option<Rc<Function>> {
+        if let Some(method) = self.methods.borrow().get(name) {
+            return Some(method.clone());
+        }
+        self.superclass.as_ref()?.find_method(name)
+    }
+
+    /// Static methods are inherited too, so `Dog.create()` finds a static
+    /// `create` declared on `Animal`.
+    pub fn find_static_method(&self, name: &Rc<String>) -> Option<Rc<Function>> {
+        if let Some(method) = self.static_methods.borrow().get(name) {
+            return Some(method.clone());
+        }
+        self.superclass.as_ref()?.find_static_method(name)
+    }
+
+    /// Static fields are looked up the same way as static methods.
+    pub fn find_static_field(&self, name: &Rc<String>) -> Option<Literal> {
+        if let Some(value) = self.static_fields.borrow().get(name) {
+            return Some(value.clone());
+        }
+        self.superclass.as_ref()?.find_static_field(name)
+    }
+
+    /// The class, this one or one of its superclasses, that declares
+    /// `method`. This is where `super` inside the method starts looking.
+    pub fn owner_of(self: &Rc<Self>, method: &Rc<Function>) -> Option<Rc<Class>> {
+        if self
+            .methods
+            .borrow()
+            .values()
+            .any(|declared| Rc::ptr_eq(declared, method))
+        {
+            return Some(self.clone());
+        }
+        self.superclass.as_ref()?.owner_of(method)
+    }
+
+    pub fn initializer(&self) -> Option<Rc<Function>> {
+        self.find_method(&Rc::new("__init__".into()))
+    }
+}
+
+impl PartialEq for Class {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: Rc<Class>,
+    pub fields: RefCell<HashMap<Rc<String>, Literal>>,
+    /// Set by `freeze()`, after which fields can be read but not set.
+    pub frozen: Cell<bool>,
+}
+
+impl Instance {
+    pub fn new(class: Rc<Class>) -> Self {
+        Self {
+            class,
+            fields: Default::default(),
+            frozen: Cell::new(false),
+        }
+    }
+
+    /// Looks `name` up in the fields first, then among the methods of the
+    /// class and its superclasses, which come back bound to this instance.
+    pub fn get(self: &Rc<Self>, name: &Rc<String>) -> Option<Literal> {
+        if let Some(value) = self.fields.borrow().get(name) {
+            return Some(value.clone());
+        }
+        let method = self.class.find_method(name)?;
+        Some(Rc::new(BoundMethod::new(self.clone().into(), method)).into())
+    }
+}
+
+impl PartialEq for Instance {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// A method taken off an instance, which remembers the instance to pass on
+/// as `self` when it gets called.
+#[derive(Debug, PartialEq)]
+pub struct BoundMethod {
+    pub receiver: Literal,
+    pub method: Rc<Function>,
+}
+
+impl BoundMethod {
+    pub fn new(receiver: Literal, method: Rc<Function>) -> Self {
+        Self { receiver, method }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+    use std::rc::Rc;
+
+    use super::{Array, Literal, Map, MapKey};
+    use crate::rlox::error::LoxError;
+    use crate::rlox::lox::testing::{compile_line, run, vm};
+
+    fn string(s: &str) -> Literal {
+        Rc::new(s.to_string()).into()
+    }
+
+    #[test]
+    fn compare_orders_numbers_and_strings() {
+        assert_eq!(Literal::from(1.0).compare(&2.0.into()), Ok(Ordering::Less));
+        assert_eq!(Literal::from(2.0).compare(&2.0.into()), Ok(Ordering::Equal));
+        assert_eq!(string("b").compare(&string("a")), Ok(Ordering::Greater));
+        assert_eq!(string("a").compare(&string("ab")), Ok(Ordering::Less));
+    }
+
+    #[test]
+    fn compare_rejects_mixed_types() {
+        assert!(Literal::from(1.0).compare(&string("1")).is_err());
+        assert!(Literal::Nil.compare(&Literal::Nil).is_err());
+        assert!(Literal::from(f64::NAN).compare(&1.0.into()).is_err());
+    }
+
+    #[test]
+    fn functions_print_their_kind_and_arity() {
+        let source =
+            "func add(a, b) { return a + b; } let l = func(x) { return x; }; print add, l;";
+        assert_eq!(run(source), Ok("<fn add/2> <lambda/1>\n".into()));
+        assert_eq!(run("print sb_new;"), Ok("<native fn sb_new/0>\n".into()));
+    }
+
+    #[test]
+    fn equality_is_ieee_for_numbers_and_identity_for_mutable_values() {
+        // NaN can't be written in Lox, as dividing by zero is an error
+        let nan = Literal::from(f64::NAN);
+        assert!(!nan.is_equal(&nan));
+        assert!(Literal::from(1.0).is_equal(&1.0.into()));
+        let source = "let a = sb_new(); let b = sb_new(); let c = a; print a == b, a == c;";
+        assert_eq!(run(source), Ok("false true\n".into()));
+        assert_eq!(
+            run("let a = [1]; print a == [1], a == a;"),
+            Ok("false true\n".into())
+        );
+    }
+
+    #[test]
+    fn literals_convert_to_rust_values() {
+        assert_eq!(f64::try_from(Literal::from(1.5)).unwrap(), 1.5);
+        assert!(bool::try_from(Literal::Bool(true)).unwrap());
+        assert_eq!(String::try_from(string("a")).unwrap(), "a");
+
+        match f64::try_from(string("1")) {
+            Err(LoxError::UnexpectedError { message }) => {
+                assert_eq!(message, "Expect a number, but got `1`.")
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert!(bool::try_from(Literal::Nil).is_err());
+        assert!(String::try_from(Literal::from(1.0)).is_err());
+    }
+
+    #[test]
+    fn arrays_and_maps_convert_to_their_shared_contents() {
+        let (mut vm, _) = vm();
+        let function = compile_line("[1, [2]];").unwrap();
+        let value = vm.interpret(function).unwrap();
+        let array = Array::try_from(&value).unwrap();
+        assert_eq!(array.borrow().len(), 2);
+        array.borrow_mut().push(3.0.into());
+        assert_eq!(value.to_string(), "[1, [2], 3]");
+
+        let function = compile_line("let m = {\"a\": 1}; m;").unwrap();
+        let value = vm.interpret(function).unwrap();
+        let map = Map::try_from(&value).unwrap();
+        let key = MapKey::new(&string("a")).unwrap();
+        assert_eq!(map.borrow()[&key], Literal::from(1.0));
+
+        match Map::try_from(&array.borrow()[1]) {
+            Err(LoxError::UnexpectedError { message }) => {
+                assert_eq!(message, "Expect a map, but got `[2]`.")
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert!(Array::try_from(&Literal::Nil).is_err());
+    }
+
+    #[test]
+    fn arrays_index_and_print() {
+        let source = "let a = [1, \"b\", [true, nil]]; a[0] = a[0] + 1; print a, a[2][0];";
+        assert_eq!(run(source), Ok("[2, b, [true, nil]] true\n".into()));
+        let err = run("let a = [1, 2];\nprint a[2];").unwrap_err();
+        assert_eq!(err, "Index 2 is out of bounds for an array of length 2.");
+    }
+
+    #[test]
+    fn printing_stops_at_cycles_and_deep_nesting() {
+        let source = "let a = [1]; a[0] = a; let m = {}; m[\"self\"] = m; print a, m;";
+        assert_eq!(run(source), Ok("[[...]] {self: {...}}\n".into()));
+        // a collection seen twice without containing itself is printed whole
+        assert_eq!(run("let b = [1]; print [b, b];"), Ok("[[1], [1]]\n".into()));
+
+        let deep = "let x = 0; let i = 0; while (i < 1000) { x = [x]; i = i + 1; } print x;";
+        let expected = format!("{}[...]{}\n", "[".repeat(32), "]".repeat(32));
+        assert_eq!(run(deep), Ok(expected));
+    }
+
+    #[test]
+    fn maps_get_and_set_string_and_number_keys() {
+        let source = r#"let m = {"a": 1, 2: "two"}; m["b"] = 3; print m, m["a"], m[2], m["x"];"#;
+        assert_eq!(run(source), Ok("{2: two, a: 1, b: 3} 1 two nil\n".into()));
+        let err = run("let m = {}; m[nil] = 1;").unwrap_err();
+        assert!(err.starts_with("Map key must be a string or a number"));
+    }
+}